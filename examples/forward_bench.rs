@@ -0,0 +1,169 @@
+//! `cargo run --release --example forward_bench -- [--connections N] [--concurrency N] [--exchanges N] [--size N] [--latency-ms N]`,
+//! compares opening a portforward websocket per connection with sharing one between several connections.
+//!
+//! Runs against an in-process mock kubelet speaking the portforward channel protocol over an in-memory
+//! websocket, the api server round trips of opening a websocket are simulated by a fixed delay.
+//! `sessions` counts the websockets opened, the api server requests a mode costs.
+
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::{Duration, Instant};
+use anyhow::Context;
+use futures::{SinkExt, StreamExt};
+use tokio::io::{AsyncReadExt, AsyncWriteExt, DuplexStream};
+use tokio::runtime::Runtime;
+use tokio::sync::Semaphore;
+use tokio::task::JoinSet;
+use tokio_tungstenite::tungstenite::Message;
+use tokio_tungstenite::tungstenite::protocol::Role;
+use tokio_tungstenite::WebSocketStream;
+use rust_k8_ui::k8ui::forward_pool::{Session, SessionPool, SLOTS_PER_SESSION};
+
+const PORT: u16 = 5432;
+
+struct BenchOptions {
+    connections: usize,
+    concurrency: usize,
+    exchanges: usize,
+    size: usize,
+    latency: Duration,
+}
+
+impl BenchOptions {
+    fn parse(args: &[String]) -> anyhow::Result<Self> {
+        let mut options = Self { connections: 500, concurrency: 16, exchanges: 4, size: 512, latency: Duration::from_millis(20) };
+        let mut args = args.iter();
+        while let Some(arg) = args.next() {
+            let value = args.next().with_context(|| format!("{} needs a value", arg))?;
+            let value: usize = value.parse().with_context(|| format!("{} is not a number", value))?;
+            match arg.as_str() {
+                "--connections" => options.connections = value,
+                "--concurrency" => options.concurrency = value.max(1),
+                "--exchanges" => options.exchanges = value,
+                "--size" => options.size = value,
+                "--latency-ms" => options.latency = Duration::from_millis(value as u64),
+                _ => anyhow::bail!("unknown option {}, expected --connections, --concurrency, --exchanges, --size or --latency-ms", arg),
+            }
+        }
+        Ok(options)
+    }
+}
+
+#[derive(Clone, Copy)]
+enum Mode {
+    PerConnection,
+    Pooled,
+}
+
+struct BenchResult {
+    elapsed: Duration,
+    sessions: usize,
+    /// Time each connection waited for its pod stream, sorted.
+    setup: Vec<Duration>,
+}
+
+fn main() -> anyhow::Result<()> {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let options = BenchOptions::parse(&args)?;
+    println!("{} connections, {} at a time, {} exchanges of {} bytes each, {}ms to open a websocket",
+             options.connections, options.concurrency, options.exchanges, options.size, options.latency.as_millis());
+    println!("{:<16}{:>10}{:>12}{:>10}{:>12}{:>12}", "mode", "sessions", "elapsed", "conn/s", "setup p50", "setup p99");
+
+    let runtime = Runtime::new()?;
+    for (name, mode) in [("per-connection", Mode::PerConnection), ("pooled", Mode::Pooled)] {
+        let result = runtime.block_on(bench(mode, &options))?;
+        let percentile = |p: usize| result.setup.get(result.setup.len().saturating_sub(1) * p / 100).copied().unwrap_or_default();
+        println!("{:<16}{:>10}{:>10.0}ms{:>10.0}{:>10.1}ms{:>10.1}ms",
+                 name, result.sessions,
+                 result.elapsed.as_secs_f64() * 1000.0,
+                 options.connections as f64 / result.elapsed.as_secs_f64(),
+                 percentile(50).as_secs_f64() * 1000.0,
+                 percentile(99).as_secs_f64() * 1000.0);
+    }
+    Ok(())
+}
+
+async fn bench(mode: Mode, options: &BenchOptions) -> anyhow::Result<BenchResult> {
+    let latency = options.latency;
+    let opened = Arc::new(AtomicUsize::new(0));
+    let pool_opened = opened.clone();
+    let pool = SessionPool::new(Box::new(move || {
+        pool_opened.fetch_add(1, Ordering::Relaxed);
+        Box::pin(mock_session(latency, SLOTS_PER_SESSION))
+    }));
+    let limit = Arc::new(Semaphore::new(options.concurrency));
+    let mut connections = JoinSet::new();
+
+    let started = Instant::now();
+    for _ in 0..options.connections {
+        let permit = limit.clone().acquire_owned().await?;
+        let pool = pool.clone();
+        let (exchanges, size) = (options.exchanges, options.size);
+        connections.spawn(async move {
+            let opening = Instant::now();
+            let stream = match mode {
+                // a websocket for the connection alone, like `Api::portforward`
+                Mode::PerConnection => mock_session(latency, 1).await?.take_slot().context("session without a slot")?,
+                Mode::Pooled => pool.take().await?,
+            };
+            let setup = opening.elapsed();
+            exchange(stream, exchanges, size).await?;
+            drop(permit);
+            anyhow::Ok(setup)
+        });
+    }
+    let mut setup = Vec::with_capacity(options.connections);
+    while let Some(connection) = connections.join_next().await {
+        setup.push(connection??);
+    }
+    let elapsed = started.elapsed();
+    setup.sort();
+
+    let sessions = match mode {
+        Mode::PerConnection => options.connections,
+        Mode::Pooled => opened.load(Ordering::Relaxed),
+    };
+    Ok(BenchResult { elapsed, sessions, setup })
+}
+
+/// Request and response round trips like a chatty database client.
+async fn exchange(mut stream: DuplexStream, exchanges: usize, size: usize) -> anyhow::Result<()> {
+    let request = vec![7u8; size];
+    let mut response = vec![0u8; size];
+    for _ in 0..exchanges {
+        stream.write_all(&request).await?;
+        stream.read_exact(&mut response).await?;
+        anyhow::ensure!(response == request, "echo differs from the request");
+    }
+    Ok(())
+}
+
+/// Opens a websocket with `slots` channel pairs to a mock kubelet after the simulated api server delay.
+async fn mock_session(latency: Duration, slots: usize) -> anyhow::Result<Arc<Session>> {
+    tokio::time::sleep(latency).await;
+    let (client, server) = tokio::io::duplex(1024 * 1024);
+    let client = WebSocketStream::from_raw_socket(client, Role::Client, None).await;
+    let server = WebSocketStream::from_raw_socket(server, Role::Server, None).await;
+    tokio::spawn(async move {
+        if let Err(e) = mock_kubelet(server, slots).await {
+            println!("mock kubelet failed {:#}", e);
+        }
+    });
+    Ok(Session::start(client, PORT, slots))
+}
+
+/// Announces the port on every data and error channel and echoes the data channels back.
+async fn mock_kubelet(mut ws: WebSocketStream<DuplexStream>, slots: usize) -> anyhow::Result<()> {
+    let [low, high] = PORT.to_le_bytes();
+    for channel in 0..slots as u8 * 2 {
+        ws.send(Message::binary(vec![channel, low, high])).await?;
+    }
+    while let Some(message) = ws.next().await {
+        match message? {
+            Message::Binary(frame) if frame.first().is_some_and(|channel| channel % 2 == 0) => ws.send(Message::Binary(frame)).await?,
+            Message::Close(_) => break,
+            _ => {}
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,287 @@
+//! Escape sequences in container output. SGR sequences become styled ranges of the plain text,
+//! everything else (cursor movement, erasing, titles) is dropped.
+
+use std::ops::Range;
+
+const ESC: char = '\u{1b}';
+const BEL: char = '\u{07}';
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum AnsiColor {
+    /// 0-7 normal, 8-15 bright, 16-231 the 6x6x6 cube, 232-255 grays.
+    Indexed(u8),
+    Rgb(u8, u8, u8),
+}
+
+impl AnsiColor {
+    /// xterm's palette.
+    pub fn rgb(&self) -> (u8, u8, u8) {
+        const BASIC: [(u8, u8, u8); 16] = [
+            (0x00, 0x00, 0x00), (0xcd, 0x00, 0x00), (0x00, 0xcd, 0x00), (0xcd, 0xcd, 0x00),
+            (0x00, 0x00, 0xee), (0xcd, 0x00, 0xcd), (0x00, 0xcd, 0xcd), (0xe5, 0xe5, 0xe5),
+            (0x7f, 0x7f, 0x7f), (0xff, 0x00, 0x00), (0x00, 0xff, 0x00), (0xff, 0xff, 0x00),
+            (0x5c, 0x5c, 0xff), (0xff, 0x00, 0xff), (0x00, 0xff, 0xff), (0xff, 0xff, 0xff),
+        ];
+        match *self {
+            AnsiColor::Rgb(r, g, b) => (r, g, b),
+            AnsiColor::Indexed(index @ 0..=15) => BASIC[index as usize],
+            AnsiColor::Indexed(index @ 16..=231) => {
+                let level = |value: u8| if value == 0 { 0 } else { 55 + value * 40 };
+                let index = index - 16;
+                (level(index / 36), level(index / 6 % 6), level(index % 6))
+            }
+            AnsiColor::Indexed(index) => {
+                let gray = 8 + (index - 232) * 10;
+                (gray, gray, gray)
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct AnsiStyle {
+    pub fg: Option<AnsiColor>,
+    pub bg: Option<AnsiColor>,
+    pub bold: bool,
+    pub dim: bool,
+    pub italic: bool,
+    pub underline: bool,
+    pub strikethrough: bool,
+    pub inverse: bool,
+}
+
+impl AnsiStyle {
+    /// Applies the parameters of one `ESC [ ... m` sequence.
+    fn apply(&mut self, params: &str) {
+        let mut codes = sgr_codes(params).into_iter();
+        while let Some(code) = codes.next() {
+            match code {
+                // a missing parameter means 0
+                None | Some(0) => *self = AnsiStyle::default(),
+                Some(1) => self.bold = true,
+                Some(2) => self.dim = true,
+                Some(3) => self.italic = true,
+                Some(4) | Some(21) => self.underline = true,
+                Some(7) => self.inverse = true,
+                Some(9) => self.strikethrough = true,
+                Some(22) => {
+                    self.bold = false;
+                    self.dim = false;
+                }
+                Some(23) => self.italic = false,
+                Some(24) => self.underline = false,
+                Some(27) => self.inverse = false,
+                Some(29) => self.strikethrough = false,
+                Some(code @ 30..=37) => self.fg = Some(AnsiColor::Indexed(code as u8 - 30)),
+                Some(38) => self.fg = extended_color(&mut codes),
+                Some(39) => self.fg = None,
+                Some(code @ 40..=47) => self.bg = Some(AnsiColor::Indexed(code as u8 - 40)),
+                Some(48) => self.bg = extended_color(&mut codes),
+                Some(49) => self.bg = None,
+                Some(code @ 90..=97) => self.fg = Some(AnsiColor::Indexed(code as u8 - 90 + 8)),
+                Some(code @ 100..=107) => self.bg = Some(AnsiColor::Indexed(code as u8 - 100 + 8)),
+                Some(_) => {}
+            }
+        }
+    }
+}
+
+/// `38;5;n` and `38;2;r;g;b`, also with colons as in `38:2::r:g:b` where the empty part is the color space.
+fn extended_color(codes: &mut impl Iterator<Item = Option<u16>>) -> Option<AnsiColor> {
+    let mut next = || codes.next().flatten();
+    match next()? {
+        5 => next().map(|index| AnsiColor::Indexed(index.min(255) as u8)),
+        2 => {
+            let (r, g, b) = (next()?, next()?, next()?);
+            Some(AnsiColor::Rgb(r.min(255) as u8, g.min(255) as u8, b.min(255) as u8))
+        }
+        _ => None,
+    }
+}
+
+fn sgr_codes(params: &str) -> Vec<Option<u16>> {
+    let mut codes = Vec::new();
+    for param in params.split(';') {
+        match param.split_once(':') {
+            // colon sub parameters carry a color space id after the mode, dropped to look like the semicolon form
+            Some((code, rest)) => {
+                codes.push(code.parse().ok());
+                let mut parts: Vec<&str> = rest.split(':').collect();
+                if parts.first() == Some(&"2") && parts.len() == 5 {
+                    parts.remove(1);
+                }
+                codes.extend(parts.into_iter().map(|p| p.parse().ok()));
+            }
+            None => codes.push(param.parse().ok()),
+        }
+    }
+    codes
+}
+
+/// Text without escape sequences and the styled byte ranges of it, unstyled text has no range.
+pub fn parse(line: &str) -> (String, Vec<(Range<usize>, AnsiStyle)>) {
+    if !line.contains(ESC) {
+        return (line.to_owned(), Vec::new());
+    }
+    let mut text = String::with_capacity(line.len());
+    let mut spans: Vec<(Range<usize>, AnsiStyle)> = Vec::new();
+    let mut style = AnsiStyle::default();
+    let mut chars = line.char_indices().peekable();
+
+    while let Some((_, c)) = chars.next() {
+        if c != ESC {
+            let start = text.len();
+            text.push(c);
+            if style != AnsiStyle::default() {
+                match spans.last_mut() {
+                    Some((range, last)) if range.end == start && *last == style => range.end = text.len(),
+                    _ => spans.push((start..text.len(), style)),
+                }
+            }
+            continue;
+        }
+        match chars.next() {
+            // CSI: parameters and intermediates up to a final byte in @..~
+            Some((start, '[')) => {
+                let mut end = None;
+                for (i, c) in chars.by_ref() {
+                    if ('@'..='~').contains(&c) {
+                        end = Some((i, c));
+                        break;
+                    }
+                }
+                if let Some((end, 'm')) = end {
+                    style.apply(&line[start + 1..end]);
+                }
+            }
+            // OSC, e.g. window titles and hyperlinks, ends with BEL or ESC \
+            Some((_, ']')) => {
+                while let Some((_, c)) = chars.next() {
+                    if c == BEL {
+                        break;
+                    }
+                    if c == ESC && chars.peek().is_some_and(|(_, c)| *c == '\\') {
+                        chars.next();
+                        break;
+                    }
+                }
+            }
+            // two character sequences like ESC 7 or ESC =
+            _ => {}
+        }
+    }
+    (text, spans)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn fg(color: AnsiColor) -> AnsiStyle {
+        AnsiStyle { fg: Some(color), ..AnsiStyle::default() }
+    }
+
+    /// The style of the only span of `line`.
+    fn style_of(line: &str) -> AnsiStyle {
+        let (_, spans) = parse(line);
+        assert_eq!(spans.len(), 1, "{:?}", spans);
+        spans[0].1
+    }
+
+    #[test]
+    fn plain_text_has_no_spans() {
+        assert_eq!(parse("no colors here"), ("no colors here".to_owned(), Vec::new()));
+    }
+
+    #[test]
+    fn ranges_are_bytes_of_the_stripped_text() {
+        let (text, spans) = parse("é\u{1b}[31mü日\u{1b}[0m!");
+        assert_eq!(text, "éü日!");
+        assert_eq!(spans, vec![(2..7, fg(AnsiColor::Indexed(1)))]);
+        assert_eq!(&text[spans[0].0.clone()], "ü日");
+    }
+
+    #[test]
+    fn adjacent_text_of_one_style_is_one_span() {
+        let (text, spans) = parse("\u{1b}[1mab\u{1b}[1mc\u{1b}[32md");
+        assert_eq!(text, "abcd");
+        let bold = AnsiStyle { bold: true, ..AnsiStyle::default() };
+        assert_eq!(spans, vec![(0..3, bold), (3..4, AnsiStyle { fg: Some(AnsiColor::Indexed(2)), ..bold })]);
+    }
+
+    #[test]
+    fn empty_parameter_resets() {
+        let (text, spans) = parse("\u{1b}[1;31ma\u{1b}[mb");
+        assert_eq!(text, "ab");
+        assert_eq!(spans.len(), 1);
+        assert_eq!(spans[0].0, 0..1);
+        assert_eq!(style_of("\u{1b}[1m\u{1b}[;31mx"), fg(AnsiColor::Indexed(1)));
+        assert_eq!(sgr_codes(""), vec![None]);
+    }
+
+    #[test]
+    fn bright_and_background_colors() {
+        assert_eq!(style_of("\u{1b}[91mx"), fg(AnsiColor::Indexed(9)));
+        assert_eq!(style_of("\u{1b}[104mx").bg, Some(AnsiColor::Indexed(12)));
+        assert_eq!(style_of("\u{1b}[31;39;1mx"), AnsiStyle { bold: true, ..AnsiStyle::default() });
+    }
+
+    #[test]
+    fn indexed_colors() {
+        assert_eq!(style_of("\u{1b}[38;5;208mx"), fg(AnsiColor::Indexed(208)));
+        assert_eq!(style_of("\u{1b}[48;5;17mx").bg, Some(AnsiColor::Indexed(17)));
+        assert_eq!(style_of("\u{1b}[38:5:208mx"), fg(AnsiColor::Indexed(208)));
+        // parameters after the color still apply
+        assert_eq!(style_of("\u{1b}[38;5;1;4mx"), AnsiStyle { underline: true, ..fg(AnsiColor::Indexed(1)) });
+    }
+
+    #[test]
+    fn rgb_colors() {
+        let orange = fg(AnsiColor::Rgb(255, 128, 0));
+        assert_eq!(style_of("\u{1b}[38;2;255;128;0mx"), orange);
+        assert_eq!(style_of("\u{1b}[38:2::255:128:0mx"), orange);
+        assert_eq!(style_of("\u{1b}[38:2:255:128:0mx"), orange);
+        assert_eq!(style_of("\u{1b}[48;2;1;2;3mx").bg, Some(AnsiColor::Rgb(1, 2, 3)));
+        assert_eq!(style_of("\u{1b}[38;2;300;0;0mx"), fg(AnsiColor::Rgb(255, 0, 0)));
+    }
+
+    #[test]
+    fn incomplete_extended_colors_are_dropped() {
+        let mut codes = sgr_codes("2;1;2").into_iter();
+        assert_eq!(extended_color(&mut codes), None);
+        let mut codes = sgr_codes("9;1").into_iter();
+        assert_eq!(extended_color(&mut codes), None);
+        let (text, spans) = parse("\u{1b}[38;5mx");
+        assert_eq!((text.as_str(), spans.len()), ("x", 0));
+    }
+
+    #[test]
+    fn osc_ends_with_bel_or_string_terminator() {
+        assert_eq!(parse("a\u{1b}]0;title\u{07}b").0, "ab");
+        assert_eq!(parse("a\u{1b}]8;;https://example.com\u{1b}\\link\u{1b}]8;;\u{1b}\\b").0, "alinkb");
+        // never ended, the rest is taken by it
+        assert_eq!(parse("a\u{1b}]0;title").0, "a");
+    }
+
+    #[test]
+    fn other_sequences_are_dropped() {
+        assert_eq!(parse("\u{1b}[2K\u{1b}[1;1Ha\u{1b}7b").0, "ab");
+        assert_eq!(parse("\u{1b}[?25lx").1, Vec::new());
+    }
+
+    #[test]
+    fn csi_cut_off_at_the_end_of_the_line() {
+        assert_eq!(parse("ok\u{1b}[31"), ("ok".to_owned(), Vec::new()));
+        assert_eq!(parse("ok\u{1b}["), ("ok".to_owned(), Vec::new()));
+        assert_eq!(parse("ok\u{1b}"), ("ok".to_owned(), Vec::new()));
+    }
+
+    #[test]
+    fn palette() {
+        assert_eq!(AnsiColor::Indexed(9).rgb(), (0xff, 0, 0));
+        assert_eq!(AnsiColor::Indexed(16).rgb(), (0, 0, 0));
+        assert_eq!(AnsiColor::Indexed(231).rgb(), (255, 255, 255));
+        assert_eq!(AnsiColor::Indexed(232).rgb(), (8, 8, 8));
+        assert_eq!(AnsiColor::Indexed(255).rgb(), (238, 238, 238));
+    }
+}
@@ -1,133 +1,759 @@
-use std::cell::{Ref, RefCell};
-use std::collections::{BTreeSet, HashMap, HashSet};
-use std::ops::DerefMut;
-use std::rc::Rc;
-use std::sync::Mutex;
-use eframe::egui;
-use eframe::egui::{Context, ScrollArea, Ui, Window};
+use std::fmt;
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use std::ops::Range;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
+use k8s_openapi::chrono::{DateTime, Utc};
+use crate::k8ui::ansi;
+use crate::k8ui::ansi::AnsiStyle;
+use crate::k8ui::name_match::{NameMatcher, NameMatchMode};
+use crate::k8ui::selectors::{validate_field_selector, validate_label_selector};
 
-#[derive(Default)]
-pub struct MegaWrapper {
-    pub state: ShortKAppState,
+/// Identifies a pod across namespaces.
+#[derive(Debug, Clone, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct PodKey {
+    pub namespace: String,
+    pub name: String,
 }
 
-#[derive(Default)]
-pub struct ContainerLayouts {
-    demos: Vec<ShortKContainer>,
-    open: HashSet<String>,
+impl PodKey {
+    pub fn new(namespace: String, name: String) -> Self {
+        Self { namespace, name }
+    }
 }
 
+impl fmt::Display for PodKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}/{}", self.namespace, self.name)
+    }
+}
 
-pub struct ShortKAppState {
-    pub pods: Vec<ShortKContainer>,
-    pub filter_pod_prefix: String,
-    pub conf_file_path: String,
-    pub namespace: String,
+/// What the pod watch lists: selectors go to the api server, the name filter is applied locally.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Hash)]
+pub struct PodQuery {
+    pub name_filter: String,
+    pub name_mode: NameMatchMode,
+    /// e.g. `app=foo,tier!=db`
+    pub label_selector: String,
+    /// e.g. `status.phase=Running,spec.nodeName=node-1`
+    pub field_selector: String,
+}
 
-    pub forwards: HashMap<String, bool>,
+impl PodQuery {
+    pub fn validate(&self) -> Result<(), String> {
+        self.name_matcher()?;
+        validate_label_selector(self.label_selector.trim())?;
+        validate_field_selector(self.field_selector.trim())
+    }
 
-    pub log_windows: Vec<PopUp>,
-    pub opened_windows: HashSet<String>,
+    pub fn name_matcher(&self) -> Result<NameMatcher, String> {
+        NameMatcher::new(self.name_mode, self.name_filter.as_str())
+    }
+}
+
+/// Ties log data to the window that asked for it. Every request gets a new id,
+/// lines still arriving for a replaced request are dropped.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct LogRequestId(pub u64);
 
-    pub about_is_open: bool,
-    pub containers: ContainerLayouts,
+impl LogRequestId {
+    /// Picked by the ui so the window knows its id before any data arrives.
+    pub fn next() -> Self {
+        static NEXT: AtomicU64 = AtomicU64::new(1);
+        Self(NEXT.fetch_add(1, Ordering::Relaxed))
+    }
+}
 
+#[derive(Debug, Clone)]
+pub struct LogRequest {
+    pub id: LogRequestId,
+    pub target: LogTarget,
+    pub query: LogQuery,
+    /// Keep streaming new lines until stopped, otherwise end after the existing ones.
+    pub follow: bool,
 }
 
-impl ShortKAppState {
-    pub fn new(pods: Vec<ShortKContainer>) -> Self {
-        Self {
-            pods,
-            filter_pod_prefix: "".to_string(),
-            conf_file_path: "".to_string(),
-            namespace: "".to_string(),
-            forwards: HashMap::new(),
-            log_windows: vec![],
-            opened_windows: Default::default(),
-            about_is_open: false,
-            containers: Default::default(),
+/// Whose log a window reads.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum LogTarget {
+    Pod(PodKey),
+    /// Every pod the query lists in the current namespace scope, pods showing up later are attached while following.
+    Pods(PodQuery),
+    Workload(WorkloadKey),
+}
+
+impl LogTarget {
+    /// Lines of several pods are tagged with their source.
+    pub fn is_aggregated(&self) -> bool {
+        !matches!(self, LogTarget::Pod(_))
+    }
+}
+
+impl fmt::Display for LogTarget {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LogTarget::Pod(pod) => write!(f, "{}", pod),
+            LogTarget::Pods(query) => {
+                let parts: Vec<String> = [
+                    (!query.name_filter.trim().is_empty()).then(|| format!("{} {}", query.name_mode, query.name_filter.trim())),
+                    (!query.label_selector.trim().is_empty()).then(|| query.label_selector.trim().to_owned()),
+                    (!query.field_selector.trim().is_empty()).then(|| query.field_selector.trim().to_owned()),
+                ].into_iter().flatten().collect();
+                if parts.is_empty() { f.write_str("all pods") } else { write!(f, "pods {}", parts.join(", ")) }
+            }
+            LogTarget::Workload(workload) => write!(f, "{}", workload),
         }
     }
+}
+
+/// The container an aggregated log line came from.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct LogSource {
+    pub pod: PodKey,
+    pub container: String,
+}
+
+impl fmt::Display for LogSource {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} {}", self.pod.name, self.container)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct LogLine {
+    /// From the log timestamps, always read for aggregated logs to interleave the pods.
+    pub at: Option<DateTime<Utc>>,
+    /// Only set for aggregated logs, shared by all lines of the container.
+    pub source: Option<Arc<LogSource>>,
+    /// Without escape sequences, what search and level detection look at.
+    pub text: String,
+    /// Colors the container printed, sorted byte ranges of `text`.
+    pub styles: Vec<(Range<usize>, AnsiStyle)>,
+}
+
+impl LogLine {
+    pub fn parse(at: Option<DateTime<Utc>>, source: Option<Arc<LogSource>>, raw: &str) -> Self {
+        let (text, styles) = ansi::parse(raw);
+        Self { at, source, text, styles }
+    }
+}
 
+/// Which part of a container log to read, mirrors the parameters of the pod log endpoint.
+#[derive(Debug, Clone, PartialEq)]
+pub struct LogQuery {
+    /// `None` lets the api server pick, which only works for pods with a single container.
+    pub container: Option<String>,
+    pub tail_lines: Option<i64>,
+    pub since: Option<LogSince>,
+    /// Log of the instance before the last restart, what a CrashLoopBackOff container printed before dying.
+    pub previous: bool,
+    pub timestamps: bool,
+    pub limit_bytes: Option<i64>,
 }
 
-impl Default for ShortKAppState {
+impl Default for LogQuery {
     fn default() -> Self {
-        Self {
-            pods: Vec::new(),
-            filter_pod_prefix: "yyy".to_string(),
-            conf_file_path: "zzz".to_string(),
-            namespace: "xxx".to_string(),
-            forwards: HashMap::new(),
-            log_windows: vec![],
-            opened_windows: Default::default(),
-            about_is_open: false,
-            containers: Default::default(),
+        Self { container: None, tail_lines: Some(100), since: None, previous: false, timestamps: false, limit_bytes: None }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum LogSince {
+    Seconds(i64),
+    Time(DateTime<Utc>),
+}
+
+impl LogSince {
+    /// A relative `90s`, `15m`, `2h` or `1d`, or an RFC 3339 time like `2024-05-01T10:00:00Z`.
+    pub fn parse(text: &str) -> Result<Self, String> {
+        let text = text.trim();
+        if let Ok(time) = DateTime::parse_from_rfc3339(text) {
+            return Ok(LogSince::Time(time.with_timezone(&Utc)));
+        }
+        let unit_at = text.find(|c: char| !c.is_ascii_digit()).unwrap_or(text.len());
+        let (number, unit) = text.split_at(unit_at);
+        let number: i64 = number.parse().map_err(|_| format!("{} is neither a duration like 15m nor an RFC 3339 time", text))?;
+        let unit_seconds = match unit {
+            "" | "s" => 1,
+            "m" => 60,
+            "h" => 60 * 60,
+            "d" => 24 * 60 * 60,
+            _ => return Err(format!("Unknown unit {} in {}, use s, m, h or d", unit, text)),
+        };
+        let seconds = number.checked_mul(unit_seconds).ok_or_else(|| format!("{} is too far back", text))?;
+        if seconds <= 0 {
+            return Err("Since has to be in the past".to_owned());
         }
+        Ok(LogSince::Seconds(seconds))
     }
 }
 
 #[derive(Debug)]
-pub struct ShortKContainer {
+pub struct ShortKPod {
     pub pod_name: String,
-    //for internal purposes
-    pub age: String,
-    pub image: String,
+    pub namespace: String,
+    pub created: Option<DateTime<Utc>>,
+    pub phase: String,
+    pub conditions: Vec<PodConditionInfo>,
+    /// Top level controller found through the owner references.
+    pub owner: Option<WorkloadKey>,
+    /// Init containers first, then regular and ephemeral ones, in spec order.
+    pub containers: Vec<ShortKContainer>,
+}
+
+impl ShortKPod {
+    #[allow(clippy::too_many_arguments)]
+    pub fn new(pod_name: String, namespace: String, created: Option<DateTime<Utc>>, phase: String, conditions: Vec<PodConditionInfo>, owner: Option<WorkloadKey>,
+               containers: Vec<ShortKContainer>) -> Self {
+        Self { pod_name, namespace, created, phase, conditions, owner, containers }
+    }
+
+    pub fn phase_severity(&self) -> StatusSeverity {
+        match self.phase.as_str() {
+            "Running" | "Succeeded" => StatusSeverity::Ok,
+            "Pending" => StatusSeverity::Progressing,
+            "Failed" => StatusSeverity::Error,
+            _ => StatusSeverity::Unknown,
+        }
+    }
+
+    pub fn key(&self) -> PodKey {
+        PodKey::new(self.namespace.clone(), self.pod_name.clone())
+    }
+
+    /// Computed on every call so columns age without a refresh.
+    pub fn age(&self) -> String {
+        match self.created {
+            Some(start) => {
+                let diff = Utc::now() - start;
+                format!("{}d, {}h, {}m", diff.num_days(), diff.num_hours() - diff.num_days() * 24, diff.num_minutes() - diff.num_hours() * 60)
+            }
+            None => "".to_owned(),
+        }
+    }
+
+    pub fn restarts(&self) -> u32 {
+        self.containers.iter().map(|c| c.restarts).sum()
+    }
+}
+
+/// A Deployment, StatefulSet, DaemonSet, Job, CronJob or whatever else ends an owner chain.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct WorkloadKey {
+    pub namespace: String,
+    pub kind: String,
+    pub name: String,
+}
+
+impl fmt::Display for WorkloadKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}/{}", self.kind, self.name)
+    }
+}
+
+/// Replica counts as shown in the group header, not every kind has all of them.
+#[derive(Debug, Clone)]
+pub struct WorkloadInfo {
+    pub key: WorkloadKey,
+    pub desired: Option<i32>,
+    pub ready: Option<i32>,
+    pub updated: Option<i32>,
+    /// Pods of a Job that completed, Jobs and CronJobs don't count ready pods.
+    pub succeeded: Option<i32>,
+    /// Running Jobs of a CronJob, or running pods of a Job.
+    pub active: Option<i32>,
+}
+
+impl WorkloadInfo {
+    pub fn new(key: WorkloadKey) -> Self {
+        Self { key, desired: None, ready: None, updated: None, succeeded: None, active: None }
+    }
+
+    pub fn is_healthy(&self) -> bool {
+        match (self.desired, self.ready) {
+            (Some(desired), Some(ready)) => ready >= desired,
+            _ => true,
+        }
+    }
+
+    pub fn summary(&self) -> String {
+        [("desired", self.desired), ("ready", self.ready), ("updated", self.updated), ("active", self.active), ("succeeded", self.succeeded)].iter()
+            .filter_map(|(label, count)| count.map(|c| format!("{} {}", label, c)))
+            .collect::<Vec<_>>()
+            .join(" · ")
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct PodConditionInfo {
+    pub condition_type: String,
+    /// `True`, `False` or `Unknown`
     pub status: String,
+    pub reason: Option<String>,
+    pub message: Option<String>,
+    pub last_transition: Option<DateTime<Utc>>,
+}
+
+/// How a status should be colored.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StatusSeverity {
+    Ok,
+    Progressing,
+    Warning,
+    Error,
+    Unknown,
+}
+
+#[derive(Debug, Clone)]
+pub struct Termination {
+    pub reason: Option<String>,
+    pub message: Option<String>,
+    pub exit_code: i32,
+    pub signal: Option<i32>,
+    pub finished_at: Option<DateTime<Utc>>,
+}
+
+impl Termination {
+    /// `OOMKilled (137)`, `Completed (0)` or `Error (1)`
+    pub fn summary(&self) -> String {
+        let reason = self.reason.as_deref().unwrap_or("Terminated");
+        match self.signal {
+            Some(signal) => format!("{} ({}, signal {})", reason, self.exit_code, signal),
+            None => format!("{} ({})", reason, self.exit_code),
+        }
+    }
+}
+
+/// Everything the kubelet reports about one container, `state` is `Running`, `Waiting` or `Terminated`.
+#[derive(Debug, Clone, Default)]
+pub struct ContainerStateInfo {
+    pub state: String,
+    pub reason: Option<String>,
+    pub message: Option<String>,
+    pub started_at: Option<DateTime<Utc>>,
+    pub termination: Option<Termination>,
+    pub ready: bool,
+    pub started: Option<bool>,
+    pub last_termination: Option<Termination>,
+}
+
+/// Waiting reasons that need a human to look at the pod.
+const FAILING_REASONS: [&str; 7] = ["CrashLoopBackOff", "ImagePullBackOff", "ErrImagePull", "InvalidImageName",
+    "CreateContainerConfigError", "CreateContainerError", "RunContainerError"];
+
+impl ContainerStateInfo {
+    /// What kubectl would print: the waiting or termination reason when there is one.
+    pub fn summary(&self) -> String {
+        if let Some(termination) = &self.termination {
+            return termination.summary();
+        }
+        match &self.reason {
+            Some(reason) => reason.clone(),
+            None if self.state.is_empty() => "Unknown".to_owned(),
+            None => self.state.clone(),
+        }
+    }
+
+    pub fn severity(&self) -> StatusSeverity {
+        match self.state.as_str() {
+            "Running" if self.ready => StatusSeverity::Ok,
+            "Running" => StatusSeverity::Warning,
+            "Waiting" => match self.reason.as_deref() {
+                Some(reason) if FAILING_REASONS.contains(&reason) => StatusSeverity::Error,
+                Some("ContainerCreating") | Some("PodInitializing") | None => StatusSeverity::Progressing,
+                Some(_) => StatusSeverity::Warning,
+            },
+            "Terminated" => match &self.termination {
+                Some(termination) if termination.exit_code == 0 => StatusSeverity::Ok,
+                _ => StatusSeverity::Error,
+            },
+            _ => StatusSeverity::Unknown,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ContainerKind {
+    Init,
+    Regular,
+    Ephemeral,
+}
+
+impl fmt::Display for ContainerKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            ContainerKind::Init => "init",
+            ContainerKind::Regular => "container",
+            ContainerKind::Ephemeral => "ephemeral",
+        };
+        f.write_str(name)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct ContainerPortInfo {
+    pub name: Option<String>,
+    /// `TCP`, `UDP` or `SCTP`
+    pub protocol: String,
+    pub number: u16,
+}
+
+impl ContainerPortInfo {
+    /// The portforward subresource only carries TCP.
+    pub fn is_forwardable(&self) -> bool {
+        self.protocol == "TCP"
+    }
+}
+
+impl fmt::Display for ContainerPortInfo {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.name {
+            Some(name) => write!(f, "{} {}/{}", name, self.number, self.protocol),
+            None => write!(f, "{}/{}", self.number, self.protocol),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct ShortKContainer {
+    pub name: String,
+    pub kind: ContainerKind,
+    pub image: String,
+    pub status: ContainerStateInfo,
     pub restarts: u32,
-    pub ports: HashMap<String, u16>,
-    pub config_map: HashMap<String, String>,
-    pub secrets: HashMap<String, String>,
-    pub forward_ons: bool,
-    pub forward_on: RefCell<bool>,
-    pub forward_onm: Mutex<bool>,
+    /// In spec order, the same number can appear once per protocol.
+    pub ports: Vec<ContainerPortInfo>,
+    pub env: ResolvedEnv,
 }
 
 impl ShortKContainer {
-    pub fn new(pod_name: String, age: String, image: String, status: String, restarts: u32, ports: HashMap<String, u16>, config_map: HashMap<String, String>,
-               secrets: HashMap<String, String>) -> Self {
-        Self { pod_name, age, image, status, restarts, ports, config_map, secrets, forward_ons: false, forward_on: RefCell::new(false), forward_onm: Mutex::new(false) }
+    pub fn new(name: String, kind: ContainerKind, image: String, status: ContainerStateInfo, restarts: u32, ports: Vec<ContainerPortInfo>, env: ResolvedEnv) -> Self {
+        Self { name, kind, image, status, restarts, ports, env }
+    }
+
+    /// Splits `registry:5000/app:1.2@sha256:...` into the image and its tag or digest.
+    pub fn image_and_tag(&self) -> (&str, &str) {
+        if let Some((image, digest)) = self.image.split_once('@') {
+            return (image, digest);
+        }
+        match self.image.rsplit_once(':') {
+            Some((image, tag)) if !tag.contains('/') => (image, tag),
+            _ => (self.image.as_str(), "latest"),
+        }
     }
 }
 
+/// Where an environment variable got its value from.
+#[derive(Debug, Clone, PartialEq)]
+pub enum EnvSource {
+    Value,
+    ConfigMapKey { name: String, key: String },
+    SecretKey { name: String, key: String },
+    Field(String),
+    ResourceField { container: String, resource: String },
+    /// `envFrom` entry, the prefix is already part of the variable name.
+    ConfigMap { name: String, prefix: Option<String> },
+    Secret { name: String, prefix: Option<String> },
+    /// Set by the kubelet for a Service, named after it.
+    ServiceLink(String),
+}
 
-impl Default for ShortKContainer {
-    fn default() -> Self {
-        Self {
-            pod_name: "".to_string(),
-            age: "age".to_owned(),
-            image: "image".to_owned(),
-            status: "stub".to_owned(),
-            restarts: 1945,
-            ports: HashMap::new(),
-            config_map: HashMap::new(),
-            secrets: HashMap::new(),
-            forward_ons: false,
-            forward_on: RefCell::new(false),
-            forward_onm: Mutex::new(false),
-        }
-    }
-}
-
-#[derive(Default)]
-pub struct PopUp {
-    pub id: String,
-}
-
-impl PopUp {
-    pub fn get_id(&self) -> String {
-        self.id.clone()
-    }
-    pub fn show(&mut self, ctx: &Context, open: &mut bool, title: String) {
-        Window::new(title)
-            .default_width(600.0)
-            .default_height(400.0)
-            .vscroll(true)
-            .resizable(true)
-            .open(open)
-            .show(ctx, |ui| {
-                ui.label(egui::RichText::new("asdasd".to_string()).small().weak());
-                ui.add(egui::Separator::default().grow(8.0));
-            });
+impl fmt::Display for EnvSource {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            EnvSource::Value => f.write_str("env"),
+            EnvSource::ConfigMapKey { name, key } => write!(f, "configMapKeyRef {}[{}]", name, key),
+            EnvSource::SecretKey { name, key } => write!(f, "secretKeyRef {}[{}]", name, key),
+            EnvSource::Field(path) => write!(f, "fieldRef {}", path),
+            EnvSource::ResourceField { container, resource } => write!(f, "resourceFieldRef {}/{}", container, resource),
+            EnvSource::ConfigMap { name, prefix } => write!(f, "envFrom configMap {}{}", name, prefix.as_ref().map(|p| format!(" prefix {}", p)).unwrap_or_default()),
+            EnvSource::Secret { name, prefix } => write!(f, "envFrom secret {}{}", name, prefix.as_ref().map(|p| format!(" prefix {}", p)).unwrap_or_default()),
+            EnvSource::ServiceLink(service) => write!(f, "service link {}", service),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct EnvVarInfo {
+    pub name: String,
+    pub value: String,
+    pub source: EnvSource,
+    /// Set when a later entry with the same name wins.
+    pub shadowed: bool,
+}
+
+/// Environment of a container in the order the kubelet applies it: service links first, then `envFrom`
+/// entries, then `env`, later entries override earlier ones with the same name.
+#[derive(Debug, Clone, Default)]
+pub struct ResolvedEnv {
+    pub vars: Vec<EnvVarInfo>,
+    /// References the kubelet would refuse to start the container with.
+    pub errors: Vec<String>,
+}
+
+impl ResolvedEnv {
+    pub fn effective(&self) -> impl Iterator<Item = &EnvVarInfo> {
+        self.vars.iter().filter(|v| !v.shadowed)
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct ServiceKey {
+    pub namespace: String,
+    pub name: String,
+}
+
+impl fmt::Display for ServiceKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}/{}", self.namespace, self.name)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct ServicePortInfo {
+    pub name: Option<String>,
+    pub protocol: String,
+    pub port: u16,
+    /// Container port number or name as written in the service.
+    pub target_port: String,
+}
+
+impl ServicePortInfo {
+    pub fn is_forwardable(&self) -> bool {
+        self.protocol == "TCP"
+    }
+}
+
+impl fmt::Display for ServicePortInfo {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if let Some(name) = &self.name {
+            write!(f, "{} ", name)?;
+        }
+        write!(f, "{}/{} → {}", self.port, self.protocol, self.target_port)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct ServiceInfo {
+    pub key: ServiceKey,
+    pub service_type: String,
+    pub cluster_ip: Option<String>,
+    /// `None` for services without a selector, their endpoints are managed by hand.
+    pub selector: Option<String>,
+    pub ports: Vec<ServicePortInfo>,
+}
+
+/// Assigned by the api thread when a forward is started.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct ForwardId(pub u64);
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum BindAddress {
+    #[default]
+    Loopback,
+    /// Reachable from other machines, anyone on the network gets into the pod.
+    All,
+}
+
+impl BindAddress {
+    pub const ALL: [BindAddress; 2] = [BindAddress::Loopback, BindAddress::All];
+
+    pub fn ip(&self) -> IpAddr {
+        match self {
+            BindAddress::Loopback => IpAddr::V4(Ipv4Addr::LOCALHOST),
+            BindAddress::All => IpAddr::V4(Ipv4Addr::UNSPECIFIED),
+        }
+    }
+}
+
+impl fmt::Display for BindAddress {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            BindAddress::Loopback => f.write_str("127.0.0.1"),
+            BindAddress::All => f.write_str("0.0.0.0"),
+        }
     }
 }
+
+/// What a forward connects to, workload and service forwards pick a Ready pod for every new connection
+/// and so keep working through rollouts.
+#[derive(Debug, Clone, PartialEq)]
+pub enum ForwardTarget {
+    Pod(PodKey),
+    Workload(WorkloadKey),
+    /// The forward's remote port is the service port, the target port is resolved per backend pod.
+    Service(ServiceKey),
+}
+
+impl ForwardTarget {
+    pub fn namespace(&self) -> &str {
+        match self {
+            ForwardTarget::Pod(pod) => pod.namespace.as_str(),
+            ForwardTarget::Workload(workload) => workload.namespace.as_str(),
+            ForwardTarget::Service(service) => service.namespace.as_str(),
+        }
+    }
+
+    /// True when connections may end up in `pod`.
+    pub fn includes(&self, pod: &ShortKPod) -> bool {
+        match self {
+            ForwardTarget::Pod(key) => *key == pod.key(),
+            ForwardTarget::Workload(workload) => pod.owner.as_ref() == Some(workload),
+            ForwardTarget::Service(_) => false,
+        }
+    }
+}
+
+impl fmt::Display for ForwardTarget {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ForwardTarget::Pod(pod) => write!(f, "{}", pod),
+            ForwardTarget::Workload(workload) => write!(f, "{}/{}", workload.namespace, workload),
+            ForwardTarget::Service(service) => write!(f, "svc {}", service),
+        }
+    }
+}
+
+/// Routing changes of a workload forward, and connections that could not be forwarded.
+#[derive(Debug, Clone)]
+pub struct ForwardEvent {
+    pub at: DateTime<Utc>,
+    /// Pod new connections go to from now on.
+    pub routed_to: Option<PodKey>,
+    pub message: String,
+}
+
+/// Counters of a forward since it was started, sampled by the listener task.
+#[derive(Debug, Clone, Default)]
+pub struct ForwardTraffic {
+    pub at: DateTime<Utc>,
+    pub bytes_up: u64,
+    pub bytes_down: u64,
+    pub open: usize,
+    pub accepted: u64,
+}
+
+impl ForwardTraffic {
+    pub fn differs(&self, other: &ForwardTraffic) -> bool {
+        (self.bytes_up, self.bytes_down, self.open, self.accepted) != (other.bytes_up, other.bytes_down, other.open, other.accepted)
+    }
+}
+
+/// One accepted local connection, sent when it opens and again when it closes.
+#[derive(Debug, Clone)]
+pub struct ConnectionInfo {
+    /// Counts up per forward.
+    pub index: u64,
+    pub peer: SocketAddr,
+    pub opened: DateTime<Utc>,
+    pub closed: Option<DateTime<Utc>>,
+    pub bytes_up: u64,
+    pub bytes_down: u64,
+    pub error: Option<String>,
+}
+
+/// Traffic of a forward as shown in the forwards panel, kept by the ui.
+#[derive(Debug, Clone, Default)]
+pub struct ForwardStats {
+    pub traffic: ForwardTraffic,
+    /// Bytes per second between the last two samples.
+    pub up_rate: f64,
+    pub down_rate: f64,
+    /// Latest last.
+    pub connections: Vec<ConnectionInfo>,
+}
+
+impl ForwardStats {
+    /// Connections kept in the log, the oldest closed ones are dropped first.
+    pub const MAX_CONNECTIONS: usize = 50;
+
+    pub fn sample(&mut self, traffic: ForwardTraffic) {
+        let seconds = (traffic.at - self.traffic.at).num_milliseconds() as f64 / 1000.0;
+        if seconds > 0.0 {
+            self.up_rate = traffic.bytes_up.saturating_sub(self.traffic.bytes_up) as f64 / seconds;
+            self.down_rate = traffic.bytes_down.saturating_sub(self.traffic.bytes_down) as f64 / seconds;
+        }
+        self.traffic = traffic;
+    }
+
+    pub fn connection(&mut self, connection: ConnectionInfo) {
+        match self.connections.iter_mut().find(|c| c.index == connection.index) {
+            Some(existing) => *existing = connection,
+            None => self.connections.push(connection),
+        }
+        if self.connections.len() > Self::MAX_CONNECTIONS {
+            let position = self.connections.iter().position(|c| c.closed.is_some()).unwrap_or(0);
+            self.connections.remove(position);
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct ForwardRequest {
+    pub target: ForwardTarget,
+    pub remote_port: u16,
+    /// Defaults to the remote port, a free one is picked when it is taken.
+    pub local_port: Option<u16>,
+    pub bind: BindAddress,
+    /// Carry several connections over one portforward websocket instead of opening one per connection.
+    pub pooled: bool,
+}
+
+/// A running port forward as listed in the Active Forwards panel.
+#[derive(Debug, Clone)]
+pub struct ForwardInfo {
+    pub id: ForwardId,
+    pub target: ForwardTarget,
+    pub remote_port: u16,
+    pub local_addr: SocketAddr,
+    /// Local port that was asked for when another one had to be used.
+    pub requested_port: Option<u16>,
+    pub pooled: bool,
+    /// Latest last, only kept by the ui.
+    pub events: Vec<ForwardEvent>,
+    pub stats: ForwardStats,
+}
+
+impl ForwardInfo {
+    /// Address to open in a browser, `0.0.0.0` is not connectable on every platform.
+    pub fn url(&self) -> String {
+        let host = if self.local_addr.ip().is_unspecified() { "localhost".to_owned() } else { self.local_addr.ip().to_string() };
+        format!("http://{}:{}", host, self.local_addr.port())
+    }
+}
+
+/// A kubeconfig context as shown in the context picker.
+#[derive(Debug, Clone)]
+pub struct KubeContextInfo {
+    pub name: String,
+    pub cluster: String,
+    pub user: String,
+    pub namespace: Option<String>,
+    /// File the context was taken from when several kubeconfigs are merged.
+    pub source: Option<PathBuf>,
+}
+
+impl KubeContextInfo {
+    pub fn describe(&self) -> String {
+        format!("{} ({} / {} / {})", self.name, self.cluster, self.user, self.namespace.as_deref().unwrap_or("default"))
+    }
+}
+
+/// One kubeconfig file taking part in the merge and the entries it contributed.
+/// Entries already defined by an earlier file are not listed, the first file wins.
+#[derive(Debug, Clone)]
+pub struct KubeconfigSource {
+    pub path: PathBuf,
+    pub contexts: Vec<String>,
+    pub clusters: Vec<String>,
+    pub users: Vec<String>,
+    pub error: Option<String>,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct KubeconfigInfo {
+    pub sources: Vec<KubeconfigSource>,
+    pub contexts: Vec<KubeContextInfo>,
+    pub current_context: Option<String>,
+}
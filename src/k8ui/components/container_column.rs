@@ -1,149 +1,377 @@
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
+use std::ops::Range;
 use std::rc::Rc;
 use crossbeam::channel::{Sender};
-use eframe::egui;
-use eframe::egui::{Context, RichText, TextBuffer, TextEdit, Ui};
-use crate::k8ui::appstate::ShortKContainer;
+use eframe::egui::{Checkbox, CollapsingHeader, Color32, Context, Grid, RichText, TextFormat, TextStyle, Ui, Visuals};
+use k8s_openapi::chrono::{DateTime, Utc};
+use eframe::egui::text::LayoutJob;
+use crate::k8ui::appstate::{ContainerKind, ContainerPortInfo, EnvSource, ForwardId, ForwardInfo, ForwardRequest, ForwardTarget, LogRequestId, LogTarget, PodKey, ShortKContainer, ShortKPod, StatusSeverity, Termination};
+use crate::k8ui::components::forward_options::ForwardOptions;
 use crate::k8ui::components::log_window::LogWindow;
 use crate::k8ui::my_thread::{ApiCommand, ThreadMessage};
+use crate::k8ui::name_match::NameMatcher;
 use crate::k8ui::ui_component_bus::UiAction::PinColumn;
 use crate::k8ui::ui_component_bus::UiBus;
 use crate::k8ui::uinormdz::UNIFIED_HEIGHT;
 
 pub struct ContainerColumn {
     pub name: String,
-    pub container: Rc<ShortKContainer>,
-    forwarded: bool,
-    pub log_window: LogWindow,
-    pub log_opened: bool,
-    pub log_loading: bool,
-    pub log_text: Option<String>,
+    pub key: PodKey,
+    pub pod: Rc<ShortKPod>,
+    show_shadowed_env: bool,
+    show_service_links: bool,
+    /// By port number and protocol, a port can be declared for TCP and UDP.
+    forward_options: HashMap<(u16, String), ForwardOptions>,
+    /// One per container that had its log opened, several can be open at once.
+    pub log_windows: Vec<LogWindow>,
     pub thread_sender: Sender<ThreadMessage>,
     pub state_upstream_sender: Sender<UiBus>,
     pub is_pinned: bool,
+    /// Set when the watch reports the pod gone, pinned columns stay around.
+    pub deleted: bool,
+}
+
+fn forward_request(pod: &ShortKPod, remote_port: u16, local_port: Option<u16>, options: &ForwardOptions) -> ForwardRequest {
+    let target = match &pod.owner {
+        Some(owner) if options.follow_owner => ForwardTarget::Workload(owner.clone()),
+        _ => ForwardTarget::Pod(pod.key()),
+    };
+    ForwardRequest { target, remote_port, local_port, bind: options.bind, pooled: options.pooled }
+}
+
+/// The first regular container, the api server wants one picked for pods with several.
+fn default_log_container(pod: &ShortKPod) -> Option<String> {
+    pod.containers.iter()
+        .find(|c| c.kind == ContainerKind::Regular)
+        .or(pod.containers.first())
+        .map(|c| c.name.clone())
 }
 
 impl ContainerColumn {
-    pub fn new(container: Rc<ShortKContainer>, thread_sender: Sender<ThreadMessage>, state_upstream_sender: Sender<UiBus>) -> Self {
+    pub fn new(pod: Rc<ShortKPod>, thread_sender: Sender<ThreadMessage>, state_upstream_sender: Sender<UiBus>) -> Self {
         Self {
-            name: container.pod_name.clone(),
-            log_window: LogWindow::new(container.pod_name.clone() + " Logs"),
-            container,
-            forwarded: false,
-            log_opened: false,
-            log_text: None,
-            log_loading: false,
+            name: pod.pod_name.clone(),
+            key: pod.key(),
+            log_windows: Vec::new(),
+            pod,
+            show_shadowed_env: false,
+            show_service_links: false,
+            forward_options: HashMap::new(),
             thread_sender,
             state_upstream_sender,
             is_pinned: false,
+            deleted: false,
         }
     }
 
-    fn draw_log_window(&mut self, ctx: &Context, option: Option<String>) {
-        let Self { log_window, log_opened, .. } = self;
+    pub fn update(&mut self, pod: Rc<ShortKPod>) {
+        self.pod = pod;
+        self.deleted = false;
+    }
 
-        if *log_opened {
-            log_window.log_text = option;
-            // println!("text... {:?}", &log_window.log_text);
-            log_window.draw(ctx, log_opened);
-        }
+    pub fn log_window_mut(&mut self, id: LogRequestId) -> Option<&mut LogWindow> {
+        self.log_windows.iter_mut().find(|w| w.request == Some(id))
     }
 
-    pub fn draw(&mut self, ctx: &Context, ui: &mut Ui) {
-        self.draw_log_window(ctx, self.log_text.clone());
+    /// Opens or closes the log window of `container`, creating it on first use.
+    fn toggle_logs(&mut self, container: Option<String>) {
+        let index = match self.log_windows.iter().position(|w| w.container == container) {
+            Some(index) => index,
+            None => {
+                let title = match &container {
+                    Some(container) => format!("{} / {} Logs", self.pod.pod_name, container),
+                    None => format!("{} Logs", self.pod.pod_name),
+                };
+                self.log_windows.push(LogWindow::new(title, LogTarget::Pod(self.key.clone()), container));
+                self.log_windows.len() - 1
+            }
+        };
+        self.log_windows[index].toggle(&self.thread_sender);
+    }
 
-        if self.log_opened && !self.log_loading {
-            self.log_loading = true;
-            // println!("loading!");
+    /// `highlight` are byte ranges of the name that matched the pod filter,
+    /// containers matching one of `sidecars` start collapsed.
+    pub fn draw(&mut self, ctx: &Context, ui: &mut Ui, highlight: &[Range<usize>], sidecars: &[NameMatcher], forwards: &BTreeMap<ForwardId, ForwardInfo>) {
+        for window in self.log_windows.iter_mut() {
+            window.show(ctx, &self.pod.containers, &self.thread_sender);
         }
 
         ui.vertical(|ui| {
             ui.set_min_width(300.0);
             ui.set_min_height(UNIFIED_HEIGHT);
-            ui.label(RichText::new(self.name.as_str()).heading());
+            ui.label(highlighted_heading(ui, self.name.as_str(), highlight));
+            ui.label(RichText::new(self.key.namespace.as_str()).weak());
+            if self.deleted {
+                ui.colored_label(Color32::RED, "Deleted");
+            }
             if ui.checkbox(&mut self.is_pinned, "Pin").changed() {
-                match self.state_upstream_sender.try_send(UiBus::Action(PinColumn(self.name.clone()))) {
+                match self.state_upstream_sender.try_send(UiBus::Action(PinColumn(self.key.clone()))) {
                     Ok(_) => println!("ok send"),
                     Err(_) => println!("err send"),
                 };
             }
 
-            let mut image_tag_split = self.container.image.split_once(":").unwrap();
-
             ui.horizontal(|ui| {
-                let image_label = ui.label("Image");
-                ui.text_edit_singleline(&mut image_tag_split.0.to_owned());//.labelled_by(image_label.id);
+                ui.label("Age");
+                ui.text_edit_singleline(&mut self.pod.age());//.labelled_by(tag_label.id);
             });
 
             ui.horizontal(|ui| {
-                let tag_label = ui.label("Tag");
-                ui.text_edit_singleline(&mut image_tag_split.1.to_owned());//.labelled_by(tag_label.id);
+                ui.label("Restarts");
+                ui.text_edit_singleline(&mut self.pod.restarts().to_string());//.labelled_by(age_label.id);
             });
 
             ui.horizontal(|ui| {
-                ui.label("Age");
-                ui.text_edit_singleline(&mut self.container.age.clone());//.labelled_by(tag_label.id);
+                ui.label("Phase");
+                ui.colored_label(severity_color(self.pod.phase_severity(), ui.visuals()), self.pod.phase.as_str());
             });
 
-            ui.horizontal(|ui| {
-                ui.label("Status");
-                ui.text_edit_singleline(&mut self.container.status.to_owned());//.labelled_by(tag_label.id);
-            });
+            CollapsingHeader::new("Conditions")
+                .id_salt((&self.key, "conditions"))
+                .show(ui, |ui| {
+                    for condition in self.pod.conditions.iter() {
+                        let severity = if condition.status == "True" { StatusSeverity::Ok } else { StatusSeverity::Warning };
+                        let mut text = format!("{}: {}", condition.condition_type, condition.status);
+                        if let Some(reason) = &condition.reason {
+                            text = format!("{} ({})", text, reason);
+                        }
+                        let response = ui.colored_label(severity_color(severity, ui.visuals()), text);
+                        let details = [condition.message.clone(), condition.last_transition.map(|t| format!("since {}", since(t)))];
+                        let details: Vec<String> = details.into_iter().flatten().collect();
+                        if !details.is_empty() {
+                            response.on_hover_text(details.join("\n"));
+                        }
+                    }
+                });
+
+            if ui.button("Logs").clicked() {
+                self.toggle_logs(default_log_container(&self.pod));
+            };
+
+            let pod = Rc::clone(&self.pod);
+            for container in pod.containers.iter() {
+                let is_sidecar = sidecars.iter().any(|m| m.matches(container.name.as_str()));
+                let title = match container.kind {
+                    ContainerKind::Regular => container.name.clone(),
+                    kind => format!("{} ({})", container.name, kind),
+                };
+                let title = RichText::new(format!("{}  {}", title, container.status.summary()))
+                    .strong()
+                    .color(severity_color(container.status.severity(), ui.visuals()));
+                CollapsingHeader::new(title)
+                    .id_salt((&self.key, container.kind == ContainerKind::Init, &container.name))
+                    .default_open(!is_sidecar && container.kind == ContainerKind::Regular)
+                    .show(ui, |ui| self.draw_container(ui, container, forwards));
+            }
+        });
+    }
+
+    fn draw_container(&mut self, ui: &mut Ui, container: &ShortKContainer, forwards: &BTreeMap<ForwardId, ForwardInfo>) {
+        let (image, tag) = container.image_and_tag();
+
+        ui.horizontal(|ui| {
+            ui.label("Image");
+            ui.text_edit_singleline(&mut image.to_owned());//.labelled_by(image_label.id);
+        });
+
+        ui.horizontal(|ui| {
+            ui.label("Tag");
+            ui.text_edit_singleline(&mut tag.to_owned());//.labelled_by(tag_label.id);
+        });
+
+        let status = &container.status;
+        ui.horizontal(|ui| {
+            ui.label("Status");
+            let response = ui.colored_label(severity_color(status.severity(), ui.visuals()), RichText::new(status.summary()).strong());
+            if let Some(message) = &status.message {
+                response.on_hover_text(message);
+            }
+            if ui.small_button("Logs").clicked() {
+                self.toggle_logs(Some(container.name.clone()));
+            }
+        });
+
+        ui.horizontal(|ui| {
+            ui.label("Ready");
+            let (ready, severity) = if status.ready { ("yes", StatusSeverity::Ok) } else { ("no", StatusSeverity::Warning) };
+            ui.colored_label(severity_color(severity, ui.visuals()), ready);
+            if let Some(started) = status.started {
+                ui.label("Started");
+                ui.label(if started { "yes" } else { "no" });
+            }
+            if let Some(started_at) = status.started_at {
+                ui.label(RichText::new(format!("since {}", since(started_at))).weak());
+            }
+        });
 
+        if let Some(last) = &status.last_termination {
             ui.horizontal(|ui| {
-                ui.label("Restarts");
-                ui.text_edit_singleline(&mut self.container.restarts.clone().to_string());//.labelled_by(age_label.id);
+                ui.label("Last termination");
+                let response = ui.colored_label(severity_color(termination_severity(last), ui.visuals()), last.summary());
+                if let Some(message) = &last.message {
+                    response.on_hover_text(message);
+                }
+                if let Some(finished_at) = last.finished_at {
+                    ui.label(RichText::new(since(finished_at)).weak());
+                }
             });
+        }
 
-            if ui.button("Logs").clicked() {
-                match self.thread_sender.try_send(ThreadMessage::Api(ApiCommand::PullLogsForPodName(self.name.clone()))) {
-                    Ok(_) => println!("ok send"),
-                    Err(_) => println!("err send"),
-                };
-                self.log_opened = !self.log_opened;
-                if self.log_opened {}
+        ui.horizontal(|ui| {
+            ui.label("Restarts");
+            ui.text_edit_singleline(&mut container.restarts.to_string());//.labelled_by(age_label.id);
+        });
+
+        let ports_label = ui.label("Ports");
+        let idle: Vec<&ContainerPortInfo> = container.ports.iter()
+            .filter(|p| p.is_forwardable() && !forwards.values().any(|f| f.target.includes(&self.pod) && f.remote_port == p.number))
+            .collect();
+        if idle.len() > 1 && ui.small_button(format!("Forward {} ports", idle.len())).clicked() {
+            let requests = idle.into_iter()
+                .map(|port| {
+                    let options = self.forward_options.entry((port.number, port.protocol.clone())).or_default();
+                    // invalid local ports use the remote one
+                    forward_request(&self.pod, port.number, options.local_port().ok().flatten(), options)
+                })
+                .collect();
+            match self.thread_sender.try_send(ThreadMessage::Api(ApiCommand::PortForward(requests))) {
+                Ok(_) => println!("ok send"),
+                Err(_) => println!("err send"),
             };
+        }
+        for port in container.ports.iter() {
+            let num = &port.number;
+            // forwards are TCP, a UDP port with the same number isn't forwarded
+            let active = forwards.values().find(|f| port.is_forwardable() && f.target.includes(&self.pod) && f.remote_port == *num);
+            let options = self.forward_options.entry((*num, port.protocol.clone())).or_default();
+            let local_port = options.local_port();
 
-            let ports_label = ui.label("Ports");
-            for (typ, num) in self.container.ports.iter() {
-                if (ui.checkbox(&mut self.forwarded, format!("{}:{}", typ, num)).labelled_by(ports_label.id)).changed() {
-                    if self.forwarded {
-                        match self.thread_sender.try_send(ThreadMessage::Api(ApiCommand::PortForwardForPodNamePort(self.name.clone(), num.clone()))) {
+            ui.horizontal(|ui| {
+                let mut forwarded = active.is_some();
+                let enabled = active.is_some() || (port.is_forwardable() && local_port.is_ok());
+                let checkbox = ui.add_enabled(enabled, Checkbox::new(&mut forwarded, port.to_string()))
+                    .labelled_by(ports_label.id);
+                if !port.is_forwardable() {
+                    checkbox.clone().on_disabled_hover_text("Only TCP ports can be forwarded");
+                }
+                if checkbox.changed() {
+                    let command = match (active, &local_port) {
+                        (Some(forward), _) => Some(ApiCommand::StopPortForward(forward.id)),
+                        (None, Ok(local_port)) => Some(ApiCommand::PortForward(vec![forward_request(&self.pod, *num, *local_port, options)])),
+                        (None, Err(_)) => None,
+                    };
+                    if let Some(command) = command {
+                        match self.thread_sender.try_send(ThreadMessage::Api(command)) {
                             Ok(_) => println!("ok send"),
                             Err(_) => println!("err send"),
                         };
                     }
                 }
-            }
-            let mut theme = egui_extras::syntax_highlighting::CodeTheme::from_memory(ui.ctx(),ui.style());
-            let mut layouter = |ui: &egui::Ui, string: &str, wrap_width: f32| {
-                let mut layout_job =
-                    egui_extras::syntax_highlighting::highlight(ui.ctx(), ui.style(), &theme, string, "rs");
-                layout_job.wrap.max_width = wrap_width;
-                ui.fonts(|f| f.layout_job(layout_job))
-            };
 
-            let secrets_label = ui.label("Secrets");
-            ui.add(TextEdit::multiline(&mut join_multiline2(&self.container.secrets)).code_editor().layouter(&mut layouter)).labelled_by(secrets_label.id);
-
-            let cfm_label = ui.label("Config Map");
-            ui.add(TextEdit::multiline(&mut join_multiline2(&self.container.config_map)).code_editor().layouter(&mut layouter)).labelled_by(cfm_label.id);
-        });
+                match active {
+                    Some(forward) => {
+                        ui.hyperlink_to(forward.url(), forward.url());
+                        if let Some(requested) = forward.requested_port {
+                            ui.label(RichText::new(format!("({} taken)", requested)).weak());
+                        }
+                        if let ForwardTarget::Workload(workload) = &forward.target {
+                            ui.label(RichText::new(format!("via {}", workload)).weak());
+                        }
+                    }
+                    None if !port.is_forwardable() => {}
+                    None => {
+                        options.draw(ui, (&self.key, &container.name, *num, &port.protocol, "bind"), *num);
+                        if let Some(owner) = &self.pod.owner {
+                            ui.checkbox(&mut options.follow_owner, "follow")
+                                .on_hover_text(format!("Route every connection to a Ready pod of {}, survives rollouts", owner));
+                        }
+                    }
+                }
+            });
+        }
+        let env = &container.env;
+        CollapsingHeader::new(format!("Env ({})", env.effective().count()))
+            .id_salt((&self.key, &container.name, "env"))
+            .default_open(!env.errors.is_empty())
+            .show(ui, |ui| {
+                for error in env.errors.iter() {
+                    ui.colored_label(ui.visuals().error_fg_color, error);
+                }
+                ui.horizontal(|ui| {
+                    ui.checkbox(&mut self.show_shadowed_env, "Show overridden");
+                    ui.checkbox(&mut self.show_service_links, "Show service links")
+                        .on_hover_text("Variables the kubelet sets for the Services of the namespace");
+                });
+                // kubelet order, the last entry of a name wins
+                Grid::new((&self.key, &container.name, "env_grid")).striped(true).show(ui, |ui| {
+                    let shown = env.vars.iter()
+                        .filter(|v| self.show_shadowed_env || !v.shadowed)
+                        .filter(|v| self.show_service_links || !matches!(v.source, EnvSource::ServiceLink(_)));
+                    for var in shown {
+                        let mut name = RichText::new(&var.name).monospace();
+                        let mut value = RichText::new(&var.value).monospace();
+                        if var.shadowed {
+                            name = name.strikethrough().weak();
+                            value = value.strikethrough().weak();
+                        }
+                        ui.label(name);
+                        ui.label(value);
+                        ui.label(RichText::new(var.source.to_string()).weak());
+                        ui.end_row();
+                    }
+                });
+            });
     }
 }
 
 
-fn join_multiline(map: &HashMap<String, u32>) -> String {
-    return map.iter()
-        .map(|(k, v)| format!("{}:{}", k, v))
-        .collect::<Vec<String>>()
-        .join("\n");
+fn severity_color(severity: StatusSeverity, visuals: &Visuals) -> Color32 {
+    match severity {
+        StatusSeverity::Ok => Color32::from_rgb(0x4c, 0xaf, 0x50),
+        StatusSeverity::Progressing => Color32::from_rgb(0x42, 0xa5, 0xf5),
+        StatusSeverity::Warning => visuals.warn_fg_color,
+        StatusSeverity::Error => visuals.error_fg_color,
+        StatusSeverity::Unknown => visuals.weak_text_color(),
+    }
+}
+
+fn termination_severity(termination: &Termination) -> StatusSeverity {
+    if termination.exit_code == 0 { StatusSeverity::Ok } else { StatusSeverity::Error }
 }
 
-fn join_multiline2(map: &HashMap<String, String>) -> String {
-    return map.iter()
-        .map(|(k, v)| format!("{}:{}", k, v))
-        .collect::<Vec<String>>()
-        .join("\n");
+/// `2025-01-01 10:00:00 (3h ago)`
+fn since(time: DateTime<Utc>) -> String {
+    let diff = Utc::now() - time;
+    let ago = if diff.num_days() > 0 {
+        format!("{}d", diff.num_days())
+    } else if diff.num_hours() > 0 {
+        format!("{}h", diff.num_hours())
+    } else if diff.num_minutes() > 0 {
+        format!("{}m", diff.num_minutes())
+    } else {
+        format!("{}s", diff.num_seconds().max(0))
+    };
+    format!("{} ({} ago)", time.format("%Y-%m-%d %H:%M:%S"), ago)
 }
 
+fn highlighted_heading(ui: &Ui, name: &str, highlight: &[Range<usize>]) -> LayoutJob {
+    let font_id = TextStyle::Heading.resolve(ui.style());
+    let normal = TextFormat::simple(font_id.clone(), ui.visuals().strong_text_color());
+    let matched = TextFormat {
+        background: ui.visuals().selection.bg_fill,
+        ..TextFormat::simple(font_id, ui.visuals().selection.stroke.color)
+    };
+
+    let mut job = LayoutJob::default();
+    let mut pos = 0;
+    for range in highlight.iter().filter(|r| r.end <= name.len()) {
+        if range.start < pos {
+            continue;
+        }
+        job.append(&name[pos..range.start], 0.0, normal.clone());
+        job.append(&name[range.clone()], 0.0, matched.clone());
+        pos = range.end;
+    }
+    job.append(&name[pos..], 0.0, normal);
+    job
+}
@@ -0,0 +1,40 @@
+use std::hash::Hash;
+use std::num::ParseIntError;
+use eframe::egui::{ComboBox, TextEdit, Ui};
+use crate::k8ui::appstate::BindAddress;
+
+/// Local port and bind address picked for a port before forwarding it.
+#[derive(Default)]
+pub struct ForwardOptions {
+    /// Empty uses the remote port.
+    pub local_port: String,
+    pub bind: BindAddress,
+    /// Forward to the owning workload instead of the pod, only offered in pod columns.
+    pub follow_owner: bool,
+    pub pooled: bool,
+}
+
+impl ForwardOptions {
+    pub fn local_port(&self) -> Result<Option<u16>, ParseIntError> {
+        let local_port = self.local_port.trim();
+        if local_port.is_empty() { Ok(None) } else { local_port.parse().map(Some) }
+    }
+
+    pub fn draw(&mut self, ui: &mut Ui, id_salt: impl Hash, remote_port: u16) {
+        ui.label("local");
+        let port_edit = ui.add(TextEdit::singleline(&mut self.local_port).hint_text(remote_port.to_string()).desired_width(50.0));
+        if self.local_port().is_err() {
+            port_edit.on_hover_text("Not a port number");
+        }
+        ComboBox::from_id_salt(id_salt)
+            .selected_text(self.bind.to_string())
+            .width(80.0)
+            .show_ui(ui, |ui| {
+                for bind in BindAddress::ALL {
+                    ui.selectable_value(&mut self.bind, bind, bind.to_string());
+                }
+            });
+        ui.checkbox(&mut self.pooled, "pool")
+            .on_hover_text("Share a portforward websocket between connections opened close together, fewer api server round trips for clients opening many connections");
+    }
+}
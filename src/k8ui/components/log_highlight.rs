@@ -0,0 +1,176 @@
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
+use std::ops::Range;
+use std::sync::LazyLock;
+use eframe::egui::{Color32, FontId, Stroke, TextFormat, Visuals};
+use eframe::egui::text::LayoutJob;
+use regex::Regex;
+use crate::k8ui::ansi::{AnsiColor, AnsiStyle};
+use crate::k8ui::appstate::{LogLine, LogSource};
+
+/// Tag colors of aggregated lines, picked by hashing the pod and the container name so they stay the same across requests.
+const SOURCE_COLORS: [Color32; 8] = [
+    Color32::from_rgb(0x42, 0xa5, 0xf5),
+    Color32::from_rgb(0x66, 0xbb, 0x6a),
+    Color32::from_rgb(0xff, 0xa7, 0x26),
+    Color32::from_rgb(0xab, 0x47, 0xbc),
+    Color32::from_rgb(0x26, 0xc6, 0xda),
+    Color32::from_rgb(0xef, 0x53, 0x50),
+    Color32::from_rgb(0xd4, 0xe1, 0x57),
+    Color32::from_rgb(0xec, 0x40, 0x7a),
+];
+
+/// Upper case level words, `level=warn` / `"level":"warn"` pairs and klog prefixes like `E0102 15:04:05.000`.
+static LEVEL: LazyLock<Regex> = LazyLock::new(|| Regex::new(
+    r#"\b(?<word>FATAL|PANIC|ERROR|WARN(?:ING)?|INFO|DEBUG|TRACE)\b|(?i:"?\blevel"?\s*[=:]\s*"?(?<pair>[a-z]+))|^(?<klog>[FEWI])\d{4} \d"#
+).unwrap());
+
+/// Frames and headers of Java, Python, Go and Rust traces.
+static STACK_TRACE: LazyLock<Regex> = LazyLock::new(|| Regex::new(
+    r#"^(\s+at \S|\s+\.\.\. \d+ more|Caused by: |Traceback \(most recent call last\)|\s+File ".*", line \d+|goroutine \d+ \[|\s+\S+\.go:\d+|thread '.*' panicked at|\s+\d+: \S)"#
+).unwrap());
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum LogLevel {
+    Error,
+    Warn,
+    Info,
+    Debug,
+}
+
+impl LogLevel {
+    pub fn parse(level: &str) -> Option<Self> {
+        match level.to_ascii_lowercase().as_str() {
+            "fatal" | "panic" | "error" | "err" | "crit" | "critical" | "f" | "e" => Some(LogLevel::Error),
+            "warn" | "warning" | "w" => Some(LogLevel::Warn),
+            "info" | "notice" | "i" => Some(LogLevel::Info),
+            "debug" | "trace" => Some(LogLevel::Debug),
+            _ => None,
+        }
+    }
+
+    pub fn color(&self, visuals: &Visuals) -> Color32 {
+        match self {
+            LogLevel::Error => visuals.error_fg_color,
+            LogLevel::Warn => visuals.warn_fg_color,
+            LogLevel::Info => Color32::from_rgb(0x4c, 0xaf, 0x50),
+            LogLevel::Debug => visuals.weak_text_color(),
+        }
+    }
+}
+
+/// The first level found in the line and where it is.
+pub fn line_level(text: &str) -> Option<(LogLevel, Range<usize>)> {
+    LEVEL.captures_iter(text).find_map(|captures| {
+        let found = captures.name("word").or(captures.name("pair")).or(captures.name("klog"))?;
+        LogLevel::parse(found.as_str()).map(|level| (level, found.range()))
+    })
+}
+
+pub fn is_stack_trace(text: &str) -> bool {
+    STACK_TRACE.is_match(text)
+}
+
+/// How a line is drawn: search matches get a background, `current` is the one navigated to.
+pub struct LineStyle<'a> {
+    pub font_id: &'a FontId,
+    pub visuals: &'a Visuals,
+    pub matches: &'a [Range<usize>],
+    pub current: bool,
+    /// Draw the colors the container printed, otherwise only the levels are colored.
+    pub ansi: bool,
+}
+
+pub fn line_job(line: &LogLine, style: &LineStyle) -> LayoutJob {
+    let font_id = style.font_id;
+    let mut job = LayoutJob::default();
+    if let Some(source) = &line.source {
+        append_source(&mut job, source, font_id);
+        job.append(" ", 0.0, TextFormat::simple(font_id.clone(), style.visuals.text_color()));
+    }
+
+    let text = line.text.as_str();
+    let (text_color, level) = if is_stack_trace(text) {
+        (style.visuals.error_fg_color.gamma_multiply(0.8), None)
+    } else {
+        (style.visuals.text_color(), line_level(text))
+    };
+    let match_background = if style.current { style.visuals.selection.bg_fill } else { style.visuals.selection.bg_fill.gamma_multiply(0.5) };
+
+    let ansi: &[(Range<usize>, AnsiStyle)] = if style.ansi { &line.styles } else { &[] };
+
+    // splits the text where the level, a color or a match starts or ends, each piece gets the format of what covers it
+    let mut cuts: Vec<usize> = vec![0, text.len()];
+    cuts.extend(level.iter().flat_map(|(_, r)| [r.start, r.end]));
+    cuts.extend(ansi.iter().flat_map(|(r, _)| [r.start, r.end]));
+    cuts.extend(style.matches.iter().flat_map(|r| [r.start, r.end]));
+    cuts.sort_unstable();
+    cuts.dedup();
+    for piece in cuts.windows(2) {
+        let (start, end) = (piece[0], piece[1]);
+        let mut format = TextFormat::simple(font_id.clone(), text_color);
+        if let Some((level, range)) = &level {
+            if range.start <= start && end <= range.end {
+                format.color = level.color(style.visuals);
+            }
+        }
+        if let Some((_, ansi)) = ansi.iter().find(|(r, _)| r.start <= start && end <= r.end) {
+            apply_ansi(&mut format, ansi, style.visuals);
+        }
+        if style.matches.iter().any(|r| r.start <= start && end <= r.end) {
+            format.background = match_background;
+        }
+        job.append(&text[start..end], 0.0, format);
+    }
+    job
+}
+
+/// The printed style over the level colors, search matches still get their background.
+fn apply_ansi(format: &mut TextFormat, ansi: &AnsiStyle, visuals: &Visuals) {
+    let mut fg = match ansi.fg {
+        // bold used to be the only way to get bright colors, terminals still show it like that
+        Some(AnsiColor::Indexed(index @ 0..=7)) if ansi.bold => Some(ansi_color(AnsiColor::Indexed(index + 8))),
+        Some(color) => Some(ansi_color(color)),
+        None if ansi.bold => Some(visuals.strong_text_color()),
+        None => None,
+    };
+    let mut bg = ansi.bg.map(ansi_color);
+    if ansi.inverse {
+        let text = fg.unwrap_or(format.color);
+        fg = Some(bg.unwrap_or(visuals.extreme_bg_color));
+        bg = Some(text);
+    }
+    if let Some(fg) = fg {
+        format.color = fg;
+    }
+    if let Some(bg) = bg {
+        format.background = bg;
+    }
+    if ansi.dim {
+        format.color = format.color.gamma_multiply(0.6);
+    }
+    format.italics = ansi.italic;
+    if ansi.underline {
+        format.underline = Stroke::new(1.0, format.color);
+    }
+    if ansi.strikethrough {
+        format.strikethrough = Stroke::new(1.0, format.color);
+    }
+}
+
+fn ansi_color(color: AnsiColor) -> Color32 {
+    let (r, g, b) = color.rgb();
+    Color32::from_rgb(r, g, b)
+}
+
+/// `pod container` like stern prints it, the two colored separately.
+pub fn append_source(job: &mut LayoutJob, source: &LogSource, font_id: &FontId) {
+    job.append(&source.pod.name, 0.0, TextFormat::simple(font_id.clone(), source_color(&source.pod)));
+    job.append(&source.container, 6.0, TextFormat::simple(font_id.clone(), source_color(&source.container)));
+}
+
+fn source_color(value: &impl Hash) -> Color32 {
+    let mut hasher = DefaultHasher::new();
+    value.hash(&mut hasher);
+    SOURCE_COLORS[hasher.finish() as usize % SOURCE_COLORS.len()]
+}
@@ -0,0 +1,89 @@
+use std::hash::Hash;
+use eframe::egui::{Checkbox, ComboBox, Key, TextEdit, Ui};
+use crate::k8ui::appstate::{LogQuery, LogSince, ShortKContainer};
+
+/// Log query as typed in the log window, turned into a `LogQuery` when applied.
+pub struct LogQueryForm {
+    /// Empty reads the whole log.
+    pub tail_lines: String,
+    pub since: String,
+    pub limit_bytes: String,
+    pub container: Option<String>,
+    pub previous: bool,
+    pub timestamps: bool,
+}
+
+impl Default for LogQueryForm {
+    fn default() -> Self {
+        Self {
+            tail_lines: "100".to_owned(),
+            since: String::new(),
+            limit_bytes: String::new(),
+            container: None,
+            previous: false,
+            timestamps: false,
+        }
+    }
+}
+
+impl LogQueryForm {
+    pub fn query(&self) -> Result<LogQuery, String> {
+        // a tail of 0 only shows new lines when following
+        let tail_lines = optional_number(&self.tail_lines, "Tail", 0)?;
+        let limit_bytes = optional_number(&self.limit_bytes, "Limit", 1)?;
+        let since = match self.since.trim() {
+            "" => None,
+            since => Some(LogSince::parse(since)?),
+        };
+        Ok(LogQuery { container: self.container.clone(), tail_lines, since, previous: self.previous, timestamps: self.timestamps, limit_bytes })
+    }
+
+    /// Returns true when the query should be applied.
+    pub fn draw(&mut self, ui: &mut Ui, id_salt: impl Hash, containers: &[ShortKContainer]) -> bool {
+        let mut apply = false;
+        ui.horizontal(|ui| {
+            if !containers.is_empty() {
+                ComboBox::from_id_salt(id_salt)
+                    .selected_text(self.container.as_deref().unwrap_or("default container"))
+                    .show_ui(ui, |ui| {
+                        for container in containers {
+                            let label = match container.restarts {
+                                0 => container.name.clone(),
+                                restarts => format!("{} ({} restarts)", container.name, restarts),
+                            };
+                            apply |= ui.selectable_value(&mut self.container, Some(container.name.clone()), label).changed();
+                        }
+                    });
+            }
+
+            ui.label("tail");
+            let tail = ui.add(TextEdit::singleline(&mut self.tail_lines).hint_text("all").desired_width(50.0));
+            ui.label("since");
+            let since = ui.add(TextEdit::singleline(&mut self.since).hint_text("15m or RFC 3339").desired_width(140.0));
+            ui.label("limit bytes");
+            let limit = ui.add(TextEdit::singleline(&mut self.limit_bytes).hint_text("none").desired_width(70.0));
+            apply |= [tail, since, limit].iter().any(|edit| edit.lost_focus() && ui.input(|i| i.key_pressed(Key::Enter)));
+
+            apply |= ui.add(Checkbox::new(&mut self.previous, "previous"))
+                .on_hover_text("Log of the instance before the last restart")
+                .changed();
+            apply |= ui.checkbox(&mut self.timestamps, "timestamps").changed();
+            apply |= ui.button("Apply").clicked();
+        });
+        if let Err(error) = self.query() {
+            ui.colored_label(ui.visuals().error_fg_color, error);
+            return false;
+        }
+        apply
+    }
+}
+
+fn optional_number(text: &str, field: &str, min: i64) -> Result<Option<i64>, String> {
+    match text.trim() {
+        "" => Ok(None),
+        text => match text.parse() {
+            Ok(number) if number >= min => Ok(Some(number)),
+            _ => Err(format!("{} has to be a number of at least {}", field, min)),
+        },
+    }
+}
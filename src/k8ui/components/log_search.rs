@@ -0,0 +1,215 @@
+use std::hash::Hash;
+use std::ops::Range;
+use eframe::egui::{ComboBox, DragValue, Key, RichText, TextEdit, Ui};
+use regex::{Regex, RegexBuilder};
+use crate::k8ui::log_buffer::LogBuffer;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SearchMode {
+    /// All lines, matches highlighted.
+    Highlight,
+    OnlyMatching,
+    /// Matching lines and the lines around them, like `grep -C`.
+    Context,
+}
+
+impl SearchMode {
+    const ALL: [SearchMode; 3] = [SearchMode::Highlight, SearchMode::OnlyMatching, SearchMode::Context];
+
+    fn label(&self) -> &'static str {
+        match self {
+            SearchMode::Highlight => "highlight",
+            SearchMode::OnlyMatching => "only matching",
+            SearchMode::Context => "with context",
+        }
+    }
+}
+
+/// Search bar of a log window. Lines are scanned as they come in, not on every frame.
+pub struct LogSearch {
+    pub text: String,
+    pub regex: bool,
+    pub case_sensitive: bool,
+    pub mode: SearchMode,
+    pub context: usize,
+    pattern: Result<Option<Regex>, String>,
+    /// Buffer positions of the matching lines, in order.
+    matches: Vec<usize>,
+    /// Lines before this position were searched already.
+    scanned: usize,
+    /// Index into `matches` of the line navigated to.
+    current: Option<usize>,
+    scroll_to_current: bool,
+}
+
+impl Default for LogSearch {
+    fn default() -> Self {
+        Self {
+            text: String::new(),
+            regex: false,
+            case_sensitive: false,
+            mode: SearchMode::Highlight,
+            context: 3,
+            pattern: Ok(None),
+            matches: Vec::new(),
+            scanned: 0,
+            current: None,
+            scroll_to_current: false,
+        }
+    }
+}
+
+impl LogSearch {
+    pub fn reset(&mut self) {
+        self.matches.clear();
+        self.scanned = 0;
+    }
+
+    /// Searches the lines from `position` on again, for when a line was sorted in there.
+    /// The position in the matches is kept, it is off by the matches inserted before it at most.
+    pub fn invalidate_from(&mut self, position: usize) {
+        let keep = self.matches.partition_point(|p| *p < position);
+        self.matches.truncate(keep);
+        self.scanned = self.scanned.min(position);
+    }
+
+    fn compile(&mut self) {
+        self.reset();
+        self.current = None;
+        let text = self.text.as_str();
+        self.pattern = match text {
+            "" => Ok(None),
+            text => {
+                let pattern = if self.regex { text.to_owned() } else { regex::escape(text) };
+                RegexBuilder::new(&pattern)
+                    .case_insensitive(!self.case_sensitive)
+                    .build()
+                    .map(Some)
+                    .map_err(|e| e.to_string())
+            }
+        };
+    }
+
+    pub fn is_active(&self) -> bool {
+        matches!(self.pattern, Ok(Some(_)))
+    }
+
+    /// Searches the lines added since the last call and forgets the ones dropped from the buffer.
+    pub fn update(&mut self, lines: &LogBuffer) {
+        let range = lines.range();
+        if self.scanned > range.end {
+            self.reset();
+        }
+        let dropped = self.matches.partition_point(|position| *position < range.start);
+        if dropped > 0 {
+            self.matches.drain(..dropped);
+            self.current = self.current.and_then(|current| current.checked_sub(dropped));
+        }
+        let Ok(Some(pattern)) = &self.pattern else {
+            return;
+        };
+        for (position, line) in lines.iter_from(self.scanned) {
+            if pattern.is_match(&line.text) {
+                self.matches.push(position);
+            }
+        }
+        self.scanned = range.end;
+    }
+
+    /// Where the pattern matches in a line, empty without a search.
+    pub fn find(&self, text: &str) -> Vec<Range<usize>> {
+        match &self.pattern {
+            Ok(Some(pattern)) => pattern.find_iter(text).map(|m| m.range()).filter(|r| !r.is_empty()).collect(),
+            _ => Vec::new(),
+        }
+    }
+
+    /// Positions of the lines to show out of `range`, `None` shows all of them.
+    pub fn visible(&self, range: Range<usize>) -> Option<Vec<usize>> {
+        if !self.is_active() {
+            return None;
+        }
+        match self.mode {
+            SearchMode::Highlight => None,
+            SearchMode::OnlyMatching => Some(self.matches.iter().copied().filter(|position| range.contains(position)).collect()),
+            SearchMode::Context => {
+                let mut visible: Vec<usize> = Vec::new();
+                for &position in self.matches.iter().filter(|position| range.contains(position)) {
+                    let start = position.saturating_sub(self.context).max(range.start).max(visible.last().map_or(0, |last| last + 1));
+                    let end = (position + self.context + 1).min(range.end);
+                    visible.extend(start..end);
+                }
+                Some(visible)
+            }
+        }
+    }
+
+    pub fn current_line(&self) -> Option<usize> {
+        self.current.and_then(|current| self.matches.get(current).copied())
+    }
+
+    /// True once after navigating, the window scrolls the current line into view.
+    pub fn take_scroll(&mut self) -> bool {
+        std::mem::take(&mut self.scroll_to_current)
+    }
+
+    fn navigate(&mut self, forward: bool) {
+        if self.matches.is_empty() {
+            return;
+        }
+        let last = self.matches.len() - 1;
+        self.current = Some(match (self.current, forward) {
+            (None, true) => 0,
+            (None, false) => last,
+            (Some(current), true) => if current == last { 0 } else { current + 1 },
+            (Some(current), false) => if current == 0 { last } else { current - 1 },
+        });
+        self.scroll_to_current = true;
+    }
+
+    pub fn draw(&mut self, ui: &mut Ui, id_salt: impl Hash) {
+        let mut changed = false;
+        ui.horizontal(|ui| {
+            ui.label("Search");
+            let edit = ui.add(TextEdit::singleline(&mut self.text).hint_text(if self.regex { "regex" } else { "text" }).desired_width(250.0));
+            changed |= edit.changed();
+            if edit.lost_focus() && ui.input(|i| i.key_pressed(Key::Enter)) {
+                self.navigate(!ui.input(|i| i.modifiers.shift));
+                edit.request_focus();
+            }
+            changed |= ui.toggle_value(&mut self.regex, ".*").on_hover_text("Regular expression").changed();
+            changed |= ui.toggle_value(&mut self.case_sensitive, "Aa").on_hover_text("Case sensitive").changed();
+
+            ComboBox::from_id_salt(id_salt)
+                .selected_text(self.mode.label())
+                .show_ui(ui, |ui| {
+                    for mode in SearchMode::ALL {
+                        ui.selectable_value(&mut self.mode, mode, mode.label());
+                    }
+                });
+            if self.mode == SearchMode::Context {
+                ui.add(DragValue::new(&mut self.context).range(0..=100).suffix(" lines"));
+            }
+
+            if ui.button("↑").on_hover_text("Previous match (Shift+Enter)").clicked() {
+                self.navigate(false);
+            }
+            if ui.button("↓").on_hover_text("Next match (Enter)").clicked() {
+                self.navigate(true);
+            }
+            match &self.pattern {
+                Err(error) => {
+                    ui.colored_label(ui.visuals().error_fg_color, error.lines().last().unwrap_or_default());
+                }
+                Ok(Some(_)) => {
+                    let position = self.current.map_or("-".to_owned(), |current| (current + 1).to_string());
+                    ui.label(RichText::new(format!("{}/{} lines", position, self.matches.len())).weak());
+                }
+                Ok(None) => {}
+            }
+        });
+        if changed {
+            self.compile();
+        }
+    }
+}
@@ -1,35 +1,319 @@
-use eframe::egui::{Context, TextBuffer, TextEdit, Window};
+use std::time::Duration;
+use crossbeam::channel::Sender;
+use tokio::sync::oneshot;
+use eframe::egui::{Checkbox, Color32, Context, DragValue, Id, Label, RichText, ScrollArea, TextStyle, Ui, Window};
+use eframe::egui::text::LayoutJob;
+use crate::k8ui::appstate::{LogLine, LogRequest, LogRequestId, LogSource, LogTarget, ShortKContainer};
+use crate::k8ui::components::log_highlight::{append_source, line_job, LineStyle};
+use crate::k8ui::components::log_query_form::LogQueryForm;
+use crate::k8ui::components::log_search::LogSearch;
+use crate::k8ui::components::structured_log_view::StructuredLogView;
+use crate::k8ui::log_buffer::{LogBuffer, DEFAULT_MAX_BYTES};
+use crate::k8ui::my_thread::{ApiCommand, ThreadMessage};
+
+/// How often a following window repaints, lines arrive in batches anyway.
+const FOLLOW_REPAINT: Duration = Duration::from_millis(200);
+
+const MIB: usize = 1024 * 1024;
+
+/// What the user changed in the window that needs the api thread.
+enum LogControl {
+    Follow(bool),
+    /// The query changed, read the log again.
+    Reload,
+}
 
 pub struct LogWindow {
+    /// Only the title, windows are told apart by `id`.
     pub name: String,
-    pub log_text: Option<String>,
+    pub target: LogTarget,
+    /// The container the window was opened for, the query may pick another one since.
+    pub container: Option<String>,
+    id: Id,
+    pub query: LogQueryForm,
+    search: LogSearch,
+    structured: StructuredLogView,
+    /// Off shows the text without the colors the container printed.
+    ansi_colors: bool,
+    lines: LogBuffer,
+    /// Memory cap of the lines in MiB, as edited.
+    max_mib: usize,
+    /// Containers an aggregated request is attached to.
+    sources: Vec<LogSource>,
+    pub follow: bool,
+    /// Lines arriving while paused, they are put in the view on resume so it doesn't change meanwhile.
+    held: Option<LogBuffer>,
+    /// Set while the window is shown, windows keep their query and lines when closed.
+    pub opened: bool,
+    /// The running request, only its data is taken.
+    pub request: Option<LogRequestId>,
+    /// Dropping it stops the running request, also when the window goes away with its column.
+    stopper: Option<oneshot::Sender<()>>,
+    /// Why the last request ended, if it failed.
+    ended: Option<String>,
 }
 
 impl LogWindow {
-    pub fn new(name: String) -> Self {
+    pub fn new(name: String, target: LogTarget, container: Option<String>) -> Self {
+        let aggregated = target.is_aggregated();
+        let id = Id::new(("log window", &target, &container));
+        let mut query = LogQueryForm { container: container.clone(), ..LogQueryForm::default() };
+        if aggregated {
+            // a tail of each container adds up quickly
+            query.tail_lines = "10".to_owned();
+        }
         Self {
             name,
-            log_text: None,
+            target,
+            container,
+            id,
+            query,
+            search: LogSearch::default(),
+            structured: StructuredLogView::default(),
+            ansi_colors: true,
+            lines: LogBuffer::new(DEFAULT_MAX_BYTES),
+            max_mib: DEFAULT_MAX_BYTES / MIB,
+            sources: Vec::new(),
+            // tailing several pods is mostly about what they print next
+            follow: aggregated,
+            held: None,
+            opened: false,
+            request: None,
+            stopper: None,
+            ended: None,
+        }
+    }
+
+    /// Lines older than the last one are sorted in by time, streams of different pods arrive in separate batches.
+    pub fn push_lines(&mut self, lines: impl IntoIterator<Item = LogLine>) {
+        if let Some(held) = self.held.as_mut() {
+            for line in lines {
+                held.push(line);
+            }
+            return;
+        }
+        for line in lines {
+            let position = self.lines.push(line);
+            // positions after it moved
+            self.search.invalidate_from(position);
+            self.structured.invalidate_from(position);
+        }
+    }
+
+    pub fn set_sources(&mut self, sources: Vec<LogSource>) {
+        self.sources = sources;
+    }
+
+    pub fn clear(&mut self) {
+        self.lines.clear();
+        if let Some(held) = self.held.as_mut() {
+            held.clear();
+        }
+        self.search.reset();
+        self.structured.reset();
+        self.sources.clear();
+    }
+
+    pub fn request_ended(&mut self, error: Option<String>) {
+        self.request = None;
+        self.stopper = None;
+        self.ended = error;
+    }
+
+    /// Opens or closes the window, reading the log on open.
+    pub fn toggle(&mut self, thread_sender: &Sender<ThreadMessage>) {
+        self.opened = !self.opened;
+        if self.opened {
+            self.request(thread_sender);
+        } else {
+            self.stop();
+        }
+    }
+
+    /// Follows the log when the window is set to, otherwise pulls the tail once.
+    /// A request still running for the window is replaced.
+    pub fn request(&mut self, thread_sender: &Sender<ThreadMessage>) {
+        // the form shows the error
+        let Ok(query) = self.query.query() else {
+            return;
+        };
+        self.stop();
+        let id = LogRequestId::next();
+        self.clear();
+        self.request = Some(id);
+        self.held = None;
+        self.ended = None;
+        let request = LogRequest { id, target: self.target.clone(), query, follow: self.follow };
+        let (stopper, stopped) = oneshot::channel();
+        self.stopper = Some(stopper);
+        match thread_sender.try_send(ThreadMessage::Api(ApiCommand::PullLogsForPodName(request, stopped))) {
+            Ok(_) => println!("ok send"),
+            Err(_) => println!("err send"),
+        };
+    }
+
+    /// The api thread sees the request stopped without a message, which could be lost on a full channel.
+    pub fn stop(&mut self) {
+        self.request_ended(None);
+    }
+
+    /// Draws the window while opened. `containers` are offered in the query, empty for windows not tied to a pod.
+    pub fn show(&mut self, ctx: &Context, containers: &[ShortKContainer], thread_sender: &Sender<ThreadMessage>) {
+        if !self.opened {
+            return;
+        }
+        let mut opened = true;
+        let control = self.draw(ctx, &mut opened, containers);
+        self.opened = opened;
+        match control {
+            Some(LogControl::Follow(true)) | Some(LogControl::Reload) => self.request(thread_sender),
+            Some(LogControl::Follow(false)) => self.stop(),
+            None => {}
+        }
+        if !opened {
+            self.stop();
         }
     }
 
-    pub fn draw(&mut self, ctx: &Context, open: &mut bool) {
+    fn draw(&mut self, ctx: &Context, open: &mut bool, containers: &[ShortKContainer]) -> Option<LogControl> {
+        let mut control = None;
         let window = Window::new(self.name.clone())
+            .id(self.id)
             .min_width(1000.0)
-            .vscroll(true)
             .resizable(true)
             .open(open);
         window.show(ctx, |ui| {
-            let mut text = if self.log_text.is_some() {
-                self.log_text.as_ref().clone().unwrap().as_str()
+            ui.set_min_width(1000.0);
+            if self.query.draw(ui, self.id.with("container"), containers) {
+                control = Some(LogControl::Reload);
+            }
+            ui.horizontal(|ui| {
+                if ui.checkbox(&mut self.follow, "Follow").changed() {
+                    control = Some(LogControl::Follow(self.follow));
+                }
+                match &mut self.held {
+                    Some(held) => if ui.button(format!("Resume ({} new)", held.len() + held.dropped())).clicked() {
+                        let lines = held.take();
+                        self.held = None;
+                        self.push_lines(lines);
+                    },
+                    None => if self.request.is_some() && self.follow && ui.button("Pause").clicked() {
+                        self.held = Some(LogBuffer::new(self.max_mib * MIB));
+                    },
+                }
+                self.structured.update(&self.lines);
+                ui.add_enabled(self.structured.is_detected(), Checkbox::new(&mut self.structured.enabled, "Table"))
+                    .on_hover_text("Fields of JSON and logfmt lines as columns")
+                    .on_disabled_hover_text("No JSON or logfmt lines");
+                ui.checkbox(&mut self.ansi_colors, "Colors").on_hover_text("Show the colors of the log, off strips them");
+                let mut lines = format!("{} lines, {:.1} MiB", self.lines.len(), self.lines.bytes() as f64 / MIB as f64);
+                if self.lines.dropped() > 0 {
+                    lines = format!("{} ({} oldest dropped)", lines, self.lines.dropped());
+                }
+                ui.label(RichText::new(lines).weak());
+                if ui.add(DragValue::new(&mut self.max_mib).range(1..=4096).prefix("max ").suffix(" MiB"))
+                    .on_hover_text("Memory the lines may take, the oldest are dropped beyond it")
+                    .changed() {
+                    self.lines.set_max_bytes(self.max_mib * MIB);
+                    if let Some(held) = self.held.as_mut() {
+                        held.set_max_bytes(self.max_mib * MIB);
+                    }
+                }
+                if let Some(error) = &self.ended {
+                    ui.colored_label(Color32::RED, format!("Log stopped: {}", error));
+                } else if self.request.is_some() && !self.follow {
+                    ui.spinner();
+                } else if self.follow && self.request.is_none() {
+                    ui.label(RichText::new("Stream ended").weak());
+                }
+            });
+            if self.target.is_aggregated() {
+                self.draw_sources(ui);
+            }
+            if self.structured.enabled {
+                self.structured.draw(ui, self.id.with("table"), &self.lines);
             } else {
-                "No logs pulled!".as_str()
+                self.search.draw(ui, self.id.with("search"));
+                ui.separator();
+                self.draw_lines(ui);
+            }
+        });
+        if self.request.is_some() && self.held.is_none() {
+            ctx.request_repaint_after(FOLLOW_REPAINT);
+        }
+        control
+    }
+
+    /// Lays out only the rows in view, lines are one row each and long ones scroll sideways.
+    fn draw_lines(&mut self, ui: &mut Ui) {
+        self.search.update(&self.lines);
+        let shown = self.lines.range();
+        // `None` is the gap between context groups
+        let rows: Option<Vec<Option<usize>>> = self.search.visible(shown.clone()).map(|visible| {
+            let mut rows = Vec::with_capacity(visible.len());
+            for (i, position) in visible.iter().enumerate() {
+                if i > 0 && visible[i - 1] + 1 != *position {
+                    rows.push(None);
+                }
+                rows.push(Some(*position));
+            }
+            rows
+        });
+        let row_count = rows.as_ref().map_or(shown.len(), Vec::len);
+        let row_at = |row: usize| match &rows {
+            Some(rows) => rows[row],
+            None => Some(shown.start + row),
+        };
+
+        let current = self.search.current_line();
+        let font_id = TextStyle::Monospace.resolve(ui.style());
+        let visuals = ui.visuals().clone();
+        let row_height = ui.text_style_height(&TextStyle::Monospace);
+
+        // sticks to the end until scrolled up, scrolling back down sticks again
+        let mut scroll = ScrollArea::both()
+            .stick_to_bottom(true)
+            .auto_shrink(false)
+            .max_height(600.0);
+        if let Some(current) = current.filter(|_| self.search.take_scroll()) {
+            let row = match &rows {
+                Some(rows) => rows.partition_point(|row| row.is_none_or(|position| position < current)),
+                None => current.saturating_sub(shown.start),
             };
-            ui.set_min_width(1000.0);
-            let mut panel = TextEdit::multiline(&mut text);
-            panel = panel.desired_width(1000.0);
-            ui.add(panel);
-            ui.separator();
+            let step = row_height + ui.spacing().item_spacing.y;
+            scroll = scroll.vertical_scroll_offset((row as f32 * step - 300.0).max(0.0));
+        }
+
+        if self.lines.is_empty() && self.request.is_none() {
+            ui.label("No logs pulled!");
+        }
+        scroll.show_rows(ui, row_height, row_count, |ui, visible_rows| {
+            for row in visible_rows {
+                let Some(position) = row_at(row) else {
+                    // like grep, a gap between context groups
+                    ui.label(RichText::new("--").monospace().weak());
+                    continue;
+                };
+                let Some(line) = self.lines.get(position) else {
+                    continue;
+                };
+                let matches = self.search.find(&line.text);
+                let style = LineStyle { font_id: &font_id, visuals: &visuals, matches: &matches, current: current == Some(position), ansi: self.ansi_colors };
+                ui.add(Label::new(line_job(line, &style)).extend());
+            }
         });
     }
-}
\ No newline at end of file
+
+    fn draw_sources(&self, ui: &mut Ui) {
+        ui.collapsing(format!("Streaming {} containers of {}", self.sources.len(), self.target), |ui| {
+            ui.horizontal_wrapped(|ui| {
+                let font_id = TextStyle::Monospace.resolve(ui.style());
+                for source in self.sources.iter() {
+                    let mut job = LayoutJob::default();
+                    append_source(&mut job, source, &font_id);
+                    ui.label(job);
+                }
+            });
+        });
+    }
+}
@@ -1,2 +1,9 @@
 pub mod container_column;
+pub mod forward_options;
+pub mod log_highlight;
+pub mod log_query_form;
+pub mod log_search;
 pub mod log_window;
+pub mod namespace_picker;
+pub mod service_list;
+pub mod structured_log_view;
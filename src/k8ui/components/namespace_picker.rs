@@ -0,0 +1,61 @@
+use eframe::egui::{ComboBox, ScrollArea, TextEdit, Ui};
+
+pub struct NamespacePicker {
+    pub namespaces: Vec<String>,
+    pub selected: String,
+    pub all_namespaces: bool,
+    search: String,
+}
+
+impl NamespacePicker {
+    pub fn new(selected: String) -> Self {
+        Self {
+            namespaces: vec![],
+            selected,
+            all_namespaces: false,
+            search: "".to_owned(),
+        }
+    }
+
+    /// Namespace the apis should be built for, `None` in all namespaces mode.
+    pub fn scope(&self) -> Option<String> {
+        if self.all_namespaces {
+            None
+        } else {
+            Some(self.selected.clone())
+        }
+    }
+
+    /// Returns true when the selection changed.
+    pub fn draw(&mut self, ui: &mut Ui) -> bool {
+        let mut changed = ui.checkbox(&mut self.all_namespaces, "All namespaces").changed();
+
+        ui.add_enabled_ui(!self.all_namespaces, |ui| {
+            ComboBox::from_id_salt("namespace_picker")
+                .selected_text(self.selected.as_str())
+                .width(200.0)
+                .show_ui(ui, |ui| {
+                    ui.add(TextEdit::singleline(&mut self.search).hint_text("Search"));
+                    let search = self.search.to_lowercase();
+
+                    ScrollArea::vertical().max_height(300.0).show(ui, |ui| {
+                        for namespace in self.namespaces.iter().filter(|ns| ns.to_lowercase().contains(&search)) {
+                            if ui.selectable_label(*namespace == self.selected, namespace).clicked() {
+                                self.selected = namespace.clone();
+                                changed = true;
+                            }
+                        }
+                    });
+
+                    // namespaces can't always be listed, let the search text be used as is
+                    if !self.search.is_empty() && !self.namespaces.contains(&self.search)
+                        && ui.selectable_label(false, format!("Use \"{}\"", self.search)).clicked() {
+                        self.selected = self.search.clone();
+                        changed = true;
+                    }
+                });
+        });
+
+        changed
+    }
+}
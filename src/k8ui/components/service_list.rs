@@ -0,0 +1,106 @@
+use std::collections::{BTreeMap, HashMap};
+use crossbeam::channel::Sender;
+use eframe::egui::{Checkbox, Grid, RichText, ScrollArea, TextEdit, Ui};
+use crate::k8ui::appstate::{ForwardId, ForwardInfo, ForwardRequest, ForwardTarget, ServiceInfo, ServiceKey};
+use crate::k8ui::components::forward_options::ForwardOptions;
+use crate::k8ui::my_thread::{ApiCommand, ThreadMessage};
+
+/// Services of the current namespace with a forward toggle per service port.
+pub struct ServiceList {
+    /// `None` until the first list arrives.
+    pub services: Option<Vec<ServiceInfo>>,
+    search: String,
+    /// By service, port number and protocol, a port can be declared for TCP and UDP.
+    forward_options: HashMap<(ServiceKey, u16, String), ForwardOptions>,
+    thread_sender: Sender<ThreadMessage>,
+}
+
+impl ServiceList {
+    pub fn new(thread_sender: Sender<ThreadMessage>) -> Self {
+        Self {
+            services: None,
+            search: "".to_owned(),
+            forward_options: HashMap::new(),
+            thread_sender,
+        }
+    }
+
+    pub fn draw(&mut self, ui: &mut Ui, forwards: &BTreeMap<ForwardId, ForwardInfo>) {
+        let Some(services) = &self.services else {
+            ui.spinner();
+            return;
+        };
+
+        ui.horizontal(|ui| {
+            ui.label("Search");
+            ui.add(TextEdit::singleline(&mut self.search).hint_text("Service name"));
+        });
+        let search = self.search.to_lowercase();
+
+        ScrollArea::vertical().show(ui, |ui| {
+            Grid::new("services").striped(true).num_columns(5).show(ui, |ui| {
+                for service in services.iter().filter(|s| s.key.name.to_lowercase().contains(&search)) {
+                    ui.label(RichText::new(&service.key.name).strong());
+                    ui.label(RichText::new(&service.key.namespace).weak());
+                    ui.label(&service.service_type);
+                    ui.label(service.cluster_ip.as_deref().unwrap_or_default());
+                    let selector = match &service.selector {
+                        Some(selector) => RichText::new(selector).monospace(),
+                        None => RichText::new("no selector").weak(),
+                    };
+                    ui.label(selector);
+                    ui.end_row();
+
+                    for port in service.ports.iter() {
+                        let target = ForwardTarget::Service(service.key.clone());
+                        // forwards are TCP, a UDP port with the same number isn't forwarded
+                        let active = forwards.values().find(|f| port.is_forwardable() && f.target == target && f.remote_port == port.port);
+                        let options = self.forward_options.entry((service.key.clone(), port.port, port.protocol.clone())).or_default();
+                        let local_port = options.local_port();
+
+                        ui.label("");
+                        ui.horizontal(|ui| {
+                            let mut forwarded = active.is_some();
+                            let enabled = active.is_some() || (port.is_forwardable() && local_port.is_ok());
+                            let checkbox = ui.add_enabled(enabled, Checkbox::new(&mut forwarded, port.to_string()));
+                            if !port.is_forwardable() {
+                                checkbox.clone().on_disabled_hover_text("Only TCP ports can be forwarded");
+                            }
+                            if checkbox.changed() {
+                                let command = match (active, &local_port) {
+                                    (Some(forward), _) => Some(ApiCommand::StopPortForward(forward.id)),
+                                    (None, Ok(local_port)) => Some(ApiCommand::PortForward(vec![ForwardRequest {
+                                        target,
+                                        remote_port: port.port,
+                                        local_port: *local_port,
+                                        bind: options.bind,
+                                        pooled: options.pooled,
+                                    }])),
+                                    (None, Err(_)) => None,
+                                };
+                                if let Some(command) = command {
+                                    match self.thread_sender.try_send(ThreadMessage::Api(command)) {
+                                        Ok(_) => println!("ok send"),
+                                        Err(_) => println!("err send"),
+                                    };
+                                }
+                            }
+
+                            match active {
+                                Some(forward) => {
+                                    ui.hyperlink_to(forward.url(), forward.url());
+                                    if let Some(pod) = forward.events.iter().rev().find_map(|e| e.routed_to.as_ref()) {
+                                        ui.label(RichText::new(format!("via {}", pod.name)).weak());
+                                    }
+                                }
+                                None if !port.is_forwardable() => {}
+                                None => options.draw(ui, (&service.key, port.port, &port.protocol, "bind"), port.port),
+                            }
+                        });
+                        ui.end_row();
+                    }
+                }
+            });
+        });
+    }
+}
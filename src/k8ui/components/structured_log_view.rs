@@ -0,0 +1,256 @@
+use std::collections::{BTreeSet, HashSet};
+use std::hash::Hash;
+use eframe::egui::{Key, RichText, Sense, TextEdit, TextStyle, Ui};
+use egui_extras::{Column, TableBuilder};
+use serde_json::Value;
+use crate::k8ui::appstate::LogLine;
+use crate::k8ui::components::log_highlight::LogLevel;
+use crate::k8ui::log_buffer::LogBuffer;
+use crate::k8ui::structured_log::{field, field_paths, parse_fields, value_text, FieldFilter};
+
+/// Columns picked until the user picks their own, the first name of each group that the lines have.
+const DEFAULT_COLUMNS: [&[&str]; 4] = [
+    &["ts", "time", "timestamp", "@timestamp"],
+    &["level", "severity", "lvl"],
+    &["msg", "message"],
+    &["trace_id", "traceId", "trace.id"],
+];
+
+/// Columns whose values are colored like log levels.
+const LEVEL_COLUMNS: [&str; 3] = ["level", "severity", "lvl"];
+
+/// Field names offered as columns stop growing here, some loggers put ids into keys.
+const MAX_FIELDS: usize = 200;
+
+/// Table of JSON and logfmt lines. Like the search, lines are parsed once as they come in
+/// and again only for the rows on screen.
+pub struct StructuredLogView {
+    pub enabled: bool,
+    columns: Vec<String>,
+    columns_picked: bool,
+    fields: BTreeSet<String>,
+    filter_text: String,
+    filter: Result<Option<FieldFilter>, String>,
+    /// Buffer positions of the lines passing the filter, lines that aren't structured only show without a filter.
+    rows: Vec<usize>,
+    scanned: usize,
+    /// Set once a JSON or logfmt line came in.
+    detected: bool,
+    /// Positions of the rows showing their whole object.
+    expanded: HashSet<usize>,
+}
+
+impl Default for StructuredLogView {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            columns: Vec::new(),
+            columns_picked: false,
+            fields: BTreeSet::new(),
+            filter_text: String::new(),
+            filter: Ok(None),
+            rows: Vec::new(),
+            scanned: 0,
+            detected: false,
+            expanded: HashSet::new(),
+        }
+    }
+}
+
+impl StructuredLogView {
+    pub fn reset(&mut self) {
+        self.rows.clear();
+        self.scanned = 0;
+        self.detected = false;
+        self.expanded.clear();
+    }
+
+    /// Scans the lines from `position` on again, for when a line was sorted in there.
+    pub fn invalidate_from(&mut self, position: usize) {
+        let keep = self.rows.partition_point(|p| *p < position);
+        self.rows.truncate(keep);
+        self.expanded.retain(|p| *p < position);
+        self.scanned = self.scanned.min(position);
+    }
+
+    pub fn is_detected(&self) -> bool {
+        self.detected
+    }
+
+    pub fn update(&mut self, lines: &LogBuffer) {
+        let range = lines.range();
+        if self.scanned > range.end {
+            self.reset();
+        }
+        // lines dropped from the buffer
+        let dropped = self.rows.partition_point(|position| *position < range.start);
+        self.rows.drain(..dropped);
+        self.expanded.retain(|position| *position >= range.start);
+
+        let filter = self.filter.as_ref().ok().and_then(Option::as_ref);
+        let mut new_fields = false;
+        for (position, line) in lines.iter_from(self.scanned) {
+            match parse_fields(&line.text) {
+                Some((_, fields)) => {
+                    self.detected = true;
+                    if self.fields.len() < MAX_FIELDS {
+                        let mut paths = Vec::new();
+                        field_paths(&fields, "", &mut paths);
+                        for path in paths {
+                            new_fields |= self.fields.insert(path);
+                        }
+                    }
+                    if filter.is_none_or(|filter| filter.matches(&fields)) {
+                        self.rows.push(position);
+                    }
+                }
+                None if filter.is_none() => self.rows.push(position),
+                None => {}
+            }
+        }
+        self.scanned = range.end;
+        if new_fields && !self.columns_picked {
+            self.columns = self.default_columns();
+        }
+    }
+
+    fn default_columns(&self) -> Vec<String> {
+        let columns: Vec<String> = DEFAULT_COLUMNS.iter()
+            .filter_map(|names| names.iter().find(|name| self.fields.contains(**name)))
+            .map(|name| name.to_string())
+            .collect();
+        if columns.is_empty() {
+            self.fields.iter().take(4).cloned().collect()
+        } else {
+            columns
+        }
+    }
+
+    pub fn draw(&mut self, ui: &mut Ui, id_salt: impl Hash + Copy, lines: &LogBuffer) {
+        ui.horizontal(|ui| {
+            ui.menu_button(format!("Columns ({})", self.columns.len()), |ui| {
+                for name in self.fields.iter() {
+                    let mut shown = self.columns.contains(name);
+                    if ui.checkbox(&mut shown, name).changed() {
+                        self.columns_picked = true;
+                        // new columns go last, in the order they were picked
+                        if shown {
+                            self.columns.push(name.clone());
+                        } else {
+                            self.columns.retain(|c| c != name);
+                        }
+                    }
+                }
+            });
+            ui.label("Filter");
+            let edit = ui.add(TextEdit::singleline(&mut self.filter_text).hint_text("level=error && user_id=42").desired_width(350.0))
+                .on_hover_text("field=value, !=, ~ regex, < <= > >= on numbers, a bare field checks it's there; combine with && || ! and parentheses");
+            if edit.changed() || (edit.lost_focus() && ui.input(|i| i.key_pressed(Key::Enter))) {
+                self.filter = match self.filter_text.trim() {
+                    "" => Ok(None),
+                    expression => FieldFilter::parse(expression).map(Some),
+                };
+                // an invalid filter keeps the rows of the last valid one
+                if self.filter.is_ok() {
+                    self.invalidate_from(0);
+                    self.update(lines);
+                }
+            }
+            match &self.filter {
+                Err(error) => {
+                    ui.colored_label(ui.visuals().error_fg_color, error);
+                }
+                Ok(_) => {
+                    ui.label(RichText::new(format!("{} of {} rows", self.rows.len(), lines.len())).weak());
+                }
+            }
+        });
+        ui.separator();
+
+        let row_height = ui.text_style_height(&TextStyle::Monospace) + 4.0;
+        let heights = self.rows.iter()
+            .map(|position| match (self.expanded.contains(position), lines.get(*position)) {
+                (true, Some(line)) => row_height * (expanded_text(line).lines().count() + 1) as f32,
+                _ => row_height,
+            });
+
+        let mut toggled = None;
+        let mut table = TableBuilder::new(ui)
+            .id_salt(id_salt)
+            .striped(true)
+            .resizable(true)
+            .stick_to_bottom(true)
+            .max_scroll_height(600.0)
+            .sense(Sense::click());
+        for _ in 1..self.columns.len() {
+            table = table.column(Column::auto().at_least(60.0).clip(true));
+        }
+        table.column(Column::remainder().clip(true))
+            .header(row_height, |mut header| {
+                for name in self.columns.iter() {
+                    header.col(|ui| {
+                        ui.strong(name);
+                    });
+                }
+                if self.columns.is_empty() {
+                    header.col(|ui| {
+                        ui.strong("line");
+                    });
+                }
+            })
+            .body(|body| {
+                body.heterogeneous_rows(heights, |mut row| {
+                    let position = self.rows[row.index()];
+                    let Some(line) = lines.get(position) else {
+                        return;
+                    };
+                    let expanded = self.expanded.contains(&position);
+                    row.set_selected(expanded);
+                    let fields = parse_fields(&line.text).map(|(_, fields)| fields);
+                    let last = self.columns.len().max(1) - 1;
+                    for column in 0..=last {
+                        row.col(|ui| {
+                            ui.vertical(|ui| {
+                                match (&fields, self.columns.get(column)) {
+                                    (Some(fields), Some(name)) => {
+                                        let text = field(fields, name).map(value_text).unwrap_or_default();
+                                        let mut text = RichText::new(text).monospace();
+                                        if LEVEL_COLUMNS.contains(&name.as_str()) {
+                                            if let Some(level) = LogLevel::parse(text.text()) {
+                                                text = text.color(level.color(ui.visuals()));
+                                            }
+                                        }
+                                        ui.label(text);
+                                    }
+                                    // lines that aren't structured show up whole in the last column
+                                    (None, _) if column == last => {
+                                        ui.label(RichText::new(&line.text).monospace().weak());
+                                    }
+                                    _ => {}
+                                }
+                                if expanded && column == last {
+                                    ui.label(RichText::new(expanded_text(line)).monospace());
+                                }
+                            });
+                        });
+                    }
+                    if row.response().clicked() {
+                        toggled = Some(position);
+                    }
+                });
+            });
+        if let Some(position) = toggled {
+            if !self.expanded.remove(&position) {
+                self.expanded.insert(position);
+            }
+        }
+    }
+}
+
+/// The whole object of an expanded row, logfmt fields shown the same way.
+fn expanded_text(line: &LogLine) -> String {
+    match parse_fields(&line.text) {
+        Some((_, fields)) => serde_json::to_string_pretty(&Value::Object(fields)).unwrap_or_default(),
+        None => String::new(),
+    }
+}
@@ -0,0 +1,465 @@
+//! Effective container environment computed the way the kubelet does it.
+//!
+//! https://kubernetes.io/docs/tasks/inject-data-application/define-environment-variable-container/
+//! https://github.com/kubernetes/kubernetes/blob/master/pkg/kubelet/kubelet_pods.go (makeEnvironmentVariables)
+
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::time::{Duration, Instant};
+use k8s_openapi::api::core::v1::{EnvFromSource, EnvVar, EnvVarSource, Pod, ResourceRequirements, Service};
+use kube::api::ListParams;
+use crate::k8ui::appstate::{EnvSource, EnvVarInfo, ResolvedEnv};
+use crate::k8ui::k8api::KubeApis;
+
+/// ConfigMaps, Secrets and Services are read again after this long. The kubelet reads them when a container
+/// starts, the resolved environment follows later edits so they show before a restart picks them up.
+pub const SOURCES_TTL: Duration = Duration::from_secs(60);
+
+/// The api server's own service, linked into every pod.
+const MASTER_SERVICE: (&str, &str) = ("default", "kubernetes");
+
+/// ConfigMaps and Secrets found for each pod, by pod uid, and the Services of each namespace.
+/// Watch events of a pod resolve against them without fetching again until they are `SOURCES_TTL` old.
+#[derive(Default)]
+pub struct EnvCache {
+    pods: HashMap<String, PodSources>,
+    services: HashMap<String, (Instant, Vec<Service>)>,
+}
+
+struct PodSources {
+    fetched: Instant,
+    config_maps: HashMap<String, BTreeMap<String, String>>,
+    secrets: HashMap<String, BTreeMap<String, String>>,
+}
+
+impl PodSources {
+    fn new() -> Self {
+        Self { fetched: Instant::now(), config_maps: HashMap::new(), secrets: HashMap::new() }
+    }
+}
+
+impl EnvCache {
+    /// True when the sources of the pod are due to be read again.
+    pub fn is_stale(&self, uid: &str) -> bool {
+        self.pods.get(uid).is_some_and(|p| p.fetched.elapsed() >= SOURCES_TTL)
+    }
+
+    pub fn remove(&mut self, uid: &str) {
+        self.pods.remove(uid);
+    }
+
+    /// Forgets the pods not in `uids`, for deletes missed while the watch was down.
+    pub fn retain(&mut self, uids: &HashSet<&str>) {
+        self.pods.retain(|uid, _| uids.contains(uid.as_str()));
+    }
+}
+
+/// Resolves the environment of every container of one pod, ConfigMaps, Secrets and Services are fetched once per pod.
+/// Missing ones and failed fetches are tried again on the next event of the pod.
+pub struct EnvResolver<'a> {
+    pod: &'a Pod,
+    namespace: String,
+    apis: &'a KubeApis,
+    cached: &'a mut PodSources,
+    cached_services: &'a mut HashMap<String, (Instant, Vec<Service>)>,
+    config_maps: HashMap<String, Result<Option<BTreeMap<String, String>>, String>>,
+    secrets: HashMap<String, Result<Option<BTreeMap<String, String>>, String>>,
+    service_links: Option<Result<Vec<ServiceLink>, String>>,
+}
+
+/// A variable the kubelet sets for a Service.
+#[derive(Clone)]
+struct ServiceLink {
+    name: String,
+    value: String,
+    service: String,
+}
+
+impl<'a> EnvResolver<'a> {
+    pub fn new(pod: &'a Pod, apis: &'a KubeApis, cache: &'a mut EnvCache) -> Self {
+        let cached = cache.pods.entry(pod.metadata.uid.clone().unwrap_or_default()).or_insert_with(PodSources::new);
+        if cached.fetched.elapsed() >= SOURCES_TTL {
+            *cached = PodSources::new();
+        }
+        cache.services.retain(|_, (listed, _)| listed.elapsed() < SOURCES_TTL);
+        let config_maps = cached.config_maps.iter().map(|(name, data)| (name.clone(), Ok(Some(data.clone())))).collect();
+        let secrets = cached.secrets.iter().map(|(name, data)| (name.clone(), Ok(Some(data.clone())))).collect();
+        Self {
+            pod,
+            namespace: pod.metadata.namespace.clone().unwrap_or_default(),
+            apis,
+            cached,
+            cached_services: &mut cache.services,
+            config_maps,
+            secrets,
+            service_links: None,
+        }
+    }
+
+    pub async fn resolve(&mut self, container: &str, env: Option<&Vec<EnvVar>>, env_from: Option<&Vec<EnvFromSource>>) -> ResolvedEnv {
+        let mut resolved = ResolvedEnv::default();
+
+        // the kubelet sets them first, any entry of the container with the same name overrides them
+        match self.service_links().await {
+            Ok(links) => for link in links {
+                push(&mut resolved, link.name, link.value, EnvSource::ServiceLink(link.service));
+            },
+            Err(e) => resolved.errors.push(e),
+        }
+
+        for from in env_from.into_iter().flatten() {
+            let prefix = from.prefix.clone().filter(|p| !p.is_empty());
+            if let Some(config_map) = &from.config_map_ref {
+                let source = EnvSource::ConfigMap { name: config_map.name.clone(), prefix: prefix.clone() };
+                match self.config_map(&config_map.name).await {
+                    Ok(Some(data)) => push_all(&mut resolved, data, prefix.as_deref(), &source),
+                    Ok(None) if config_map.optional == Some(true) => {}
+                    Ok(None) => resolved.errors.push(format!("configmap \"{}\" not found", config_map.name)),
+                    Err(e) => resolved.errors.push(e),
+                }
+            }
+            if let Some(secret) = &from.secret_ref {
+                let source = EnvSource::Secret { name: secret.name.clone(), prefix: prefix.clone() };
+                match self.secret(&secret.name).await {
+                    Ok(Some(data)) => push_all(&mut resolved, data, prefix.as_deref(), &source),
+                    Ok(None) if secret.optional == Some(true) => {}
+                    Ok(None) => resolved.errors.push(format!("secret \"{}\" not found", secret.name)),
+                    Err(e) => resolved.errors.push(e),
+                }
+            }
+        }
+
+        for var in env.into_iter().flatten() {
+            let resolved_var = match &var.value_from {
+                Some(from) => self.value_from(container, from).await,
+                None => {
+                    // $(VAR) only sees what is defined before it, referenced values are not expanded again
+                    let defined: HashMap<&str, &str> = resolved.vars.iter()
+                        .map(|v| (v.name.as_str(), v.value.as_str()))
+                        .collect();
+                    Ok(Some((expand(var.value.as_deref().unwrap_or_default(), &defined), EnvSource::Value)))
+                }
+            };
+            match resolved_var {
+                Ok(Some((value, source))) => push(&mut resolved, var.name.clone(), value, source),
+                Ok(None) => {}
+                Err(e) => resolved.errors.push(format!("{}: {}", var.name, e)),
+            }
+        }
+
+        resolved
+    }
+
+    /// `Ok(None)` when an optional reference is missing, the variable is not set at all then.
+    async fn value_from(&mut self, container: &str, from: &EnvVarSource) -> Result<Option<(String, EnvSource)>, String> {
+        if let Some(selector) = &from.config_map_key_ref {
+            let source = EnvSource::ConfigMapKey { name: selector.name.clone(), key: selector.key.clone() };
+            let optional = selector.optional == Some(true);
+            return match self.config_map(&selector.name).await? {
+                Some(data) => match data.get(&selector.key) {
+                    Some(value) => Ok(Some((value.clone(), source))),
+                    None if optional => Ok(None),
+                    None => Err(format!("key \"{}\" not found in configmap \"{}\"", selector.key, selector.name)),
+                },
+                None if optional => Ok(None),
+                None => Err(format!("configmap \"{}\" not found", selector.name)),
+            };
+        }
+
+        if let Some(selector) = &from.secret_key_ref {
+            let source = EnvSource::SecretKey { name: selector.name.clone(), key: selector.key.clone() };
+            let optional = selector.optional == Some(true);
+            return match self.secret(&selector.name).await? {
+                Some(data) => match data.get(&selector.key) {
+                    Some(value) => Ok(Some((value.clone(), source))),
+                    None if optional => Ok(None),
+                    None => Err(format!("key \"{}\" not found in secret \"{}\"", selector.key, selector.name)),
+                },
+                None if optional => Ok(None),
+                None => Err(format!("secret \"{}\" not found", selector.name)),
+            };
+        }
+
+        if let Some(selector) = &from.field_ref {
+            let value = field_value(self.pod, &selector.field_path)?;
+            return Ok(Some((value, EnvSource::Field(selector.field_path.clone()))));
+        }
+
+        if let Some(selector) = &from.resource_field_ref {
+            let container = selector.container_name.clone().filter(|c| !c.is_empty()).unwrap_or_else(|| container.to_owned());
+            let divisor = selector.divisor.as_ref().map(|d| d.0.as_str());
+            let value = resource_value(self.resources_of(&container), &selector.resource, divisor)?;
+            return Ok(Some((value, EnvSource::ResourceField { container, resource: selector.resource.clone() })));
+        }
+
+        Err("valueFrom without a source".to_owned())
+    }
+
+    fn resources_of(&self, container: &str) -> Option<&ResourceRequirements> {
+        let spec = self.pod.spec.as_ref()?;
+        spec.containers.iter()
+            .chain(spec.init_containers.iter().flatten())
+            .find(|c| c.name == container)
+            .and_then(|c| c.resources.as_ref())
+    }
+
+    /// Variables of the Services in the pod's namespace unless `enableServiceLinks` is off, and of the api server's.
+    async fn service_links(&mut self) -> Result<Vec<ServiceLink>, String> {
+        if let Some(links) = &self.service_links {
+            return links.clone();
+        }
+        let links = self.list_service_links().await;
+        self.service_links = Some(links.clone());
+        links
+    }
+
+    async fn list_service_links(&mut self) -> Result<Vec<ServiceLink>, String> {
+        let mut services = BTreeMap::new();
+        if self.pod.spec.as_ref().and_then(|s| s.enable_service_links).unwrap_or(true) {
+            for service in self.services(&self.namespace.clone()).await? {
+                services.insert(service.metadata.name.clone().unwrap_or_default(), service);
+            }
+        }
+        let (namespace, name) = MASTER_SERVICE;
+        // a service of the same name in the pod's namespace wins
+        if let Some(master) = self.services(namespace).await?.into_iter().find(|s| s.metadata.name.as_deref() == Some(name)) {
+            services.entry(name.to_owned()).or_insert(master);
+        }
+        Ok(services.iter()
+            .flat_map(|(service_name, service)| service_link_vars(service).into_iter()
+                .map(|(name, value)| ServiceLink { name, value, service: service_name.clone() }))
+            .collect())
+    }
+
+    async fn services(&mut self, namespace: &str) -> Result<Vec<Service>, String> {
+        if let Some((_, services)) = self.cached_services.get(namespace) {
+            return Ok(services.clone());
+        }
+        let services = self.apis.namespaced::<Service>(namespace).list(&ListParams::default()).await
+            .map_err(|e| format!("service links of {}: {}", namespace, e))?
+            .items;
+        self.cached_services.insert(namespace.to_owned(), (Instant::now(), services.clone()));
+        Ok(services)
+    }
+
+    async fn config_map(&mut self, name: &str) -> Result<Option<BTreeMap<String, String>>, String> {
+        if !self.config_maps.contains_key(name) {
+            let fetched = self.apis.config_maps(&self.namespace).get_opt(name).await
+                // binaryData isn't exposed as env by the kubelet
+                .map(|cm| cm.map(|cm| cm.data.unwrap_or_default()))
+                .map_err(|e| format!("configmap \"{}\": {}", name, e));
+            if let Ok(Some(data)) = &fetched {
+                self.cached.config_maps.insert(name.to_owned(), data.clone());
+            }
+            self.config_maps.insert(name.to_owned(), fetched);
+        }
+        self.config_maps[name].clone()
+    }
+
+    async fn secret(&mut self, name: &str) -> Result<Option<BTreeMap<String, String>>, String> {
+        if !self.secrets.contains_key(name) {
+            let fetched = self.apis.secrets(&self.namespace).get_opt(name).await
+                .map(|secret| secret.map(|secret| secret.data.unwrap_or_default().into_iter()
+                    .map(|(key, value)| (key, String::from_utf8_lossy(&value.0).into_owned()))
+                    .collect::<BTreeMap<_, _>>()))
+                .map_err(|e| format!("secret \"{}\": {}", name, e));
+            if let Ok(Some(data)) = &fetched {
+                self.cached.secrets.insert(name.to_owned(), data.clone());
+            }
+            self.secrets.insert(name.to_owned(), fetched);
+        }
+        self.secrets[name].clone()
+    }
+}
+
+/// `{SVC}_SERVICE_HOST`, `{SVC}_SERVICE_PORT` and the docker link `{SVC}_PORT_*` variables of a service with a
+/// cluster ip, like `FromServices` in pkg/kubelet/envvars.
+fn service_link_vars(service: &Service) -> Vec<(String, String)> {
+    let Some(spec) = service.spec.as_ref() else {
+        return Vec::new();
+    };
+    let ip = match spec.cluster_ip.as_deref() {
+        Some(ip) if !ip.is_empty() && ip != "None" => ip,
+        _ => return Vec::new(),
+    };
+    let ports = spec.ports.as_deref().unwrap_or_default();
+    let Some(first) = ports.first() else {
+        return Vec::new();
+    };
+    let prefix = env_var_name(service.metadata.name.as_deref().unwrap_or_default());
+    let mut vars = vec![
+        (format!("{}_SERVICE_HOST", prefix), ip.to_owned()),
+        (format!("{}_SERVICE_PORT", prefix), first.port.to_string()),
+    ];
+    for port in ports {
+        if let Some(name) = port.name.as_deref().filter(|n| !n.is_empty()) {
+            vars.push((format!("{}_SERVICE_PORT_{}", prefix, env_var_name(name)), port.port.to_string()));
+        }
+    }
+    let host = |port: i32| match ip.contains(':') {
+        true => format!("[{}]:{}", ip, port),
+        false => format!("{}:{}", ip, port),
+    };
+    for (i, port) in ports.iter().enumerate() {
+        let protocol = port.protocol.as_deref().unwrap_or("TCP");
+        let url = format!("{}://{}", protocol.to_lowercase(), host(port.port));
+        if i == 0 {
+            vars.push((format!("{}_PORT", prefix), url.clone()));
+        }
+        let port_prefix = format!("{}_PORT_{}_{}", prefix, port.port, protocol.to_uppercase());
+        vars.push((port_prefix.clone(), url));
+        vars.push((format!("{}_PROTO", port_prefix), protocol.to_lowercase()));
+        vars.push((format!("{}_PORT", port_prefix), port.port.to_string()));
+        vars.push((format!("{}_ADDR", port_prefix), ip.to_owned()));
+    }
+    vars
+}
+
+/// `my-service` -> `MY_SERVICE`
+fn env_var_name(name: &str) -> String {
+    name.replace('-', "_").to_uppercase()
+}
+
+fn push_all(resolved: &mut ResolvedEnv, data: BTreeMap<String, String>, prefix: Option<&str>, source: &EnvSource) {
+    for (key, value) in data {
+        let name = format!("{}{}", prefix.unwrap_or_default(), key);
+        // the kubelet skips keys that can't be variable names
+        if name.is_empty() || name.contains('=') {
+            resolved.errors.push(format!("{}: invalid variable name \"{}\" skipped", source, name));
+            continue;
+        }
+        push(resolved, name, value, source.clone());
+    }
+}
+
+fn push(resolved: &mut ResolvedEnv, name: String, value: String, source: EnvSource) {
+    for earlier in resolved.vars.iter_mut().filter(|v| v.name == name) {
+        earlier.shadowed = true;
+    }
+    resolved.vars.push(EnvVarInfo { name, value, source, shadowed: false });
+}
+
+/// `$(VAR)` is replaced when `VAR` is defined, `$$` escapes a `$`, anything else is kept as written.
+fn expand(value: &str, defined: &HashMap<&str, &str>) -> String {
+    let mut expanded = String::with_capacity(value.len());
+    let mut rest = value;
+    while let Some(i) = rest.find('$') {
+        expanded.push_str(&rest[..i]);
+        rest = &rest[i + 1..];
+        if let Some(after) = rest.strip_prefix('$') {
+            expanded.push('$');
+            rest = after;
+            continue;
+        }
+        let reference = rest.strip_prefix('(')
+            .and_then(|r| r.find(')').map(|end| &r[..end]));
+        match reference {
+            Some(name) => {
+                match defined.get(name) {
+                    Some(value) => expanded.push_str(value),
+                    None => {
+                        expanded.push_str("$(");
+                        expanded.push_str(name);
+                        expanded.push(')');
+                    }
+                }
+                rest = &rest[name.len() + 2..];
+            }
+            None => expanded.push('$'),
+        }
+    }
+    expanded.push_str(rest);
+    expanded
+}
+
+/// Pod fields the downward api exposes through `fieldRef`.
+fn field_value(pod: &Pod, path: &str) -> Result<String, String> {
+    let meta = &pod.metadata;
+    let status = pod.status.as_ref();
+    let spec = pod.spec.as_ref();
+
+    if let Some(key) = map_key(path, "metadata.labels") {
+        return Ok(meta.labels.as_ref().and_then(|l| l.get(key)).cloned().unwrap_or_default());
+    }
+    if let Some(key) = map_key(path, "metadata.annotations") {
+        return Ok(meta.annotations.as_ref().and_then(|a| a.get(key)).cloned().unwrap_or_default());
+    }
+
+    let value = match path {
+        "metadata.name" => meta.name.clone(),
+        "metadata.namespace" => meta.namespace.clone(),
+        "metadata.uid" => meta.uid.clone(),
+        "spec.nodeName" => spec.and_then(|s| s.node_name.clone()),
+        "spec.serviceAccountName" => spec.and_then(|s| s.service_account_name.clone()),
+        "status.hostIP" => status.and_then(|s| s.host_ip.clone()),
+        "status.hostIPs" => status.and_then(|s| s.host_ips.as_ref())
+            .map(|ips| ips.iter().map(|ip| ip.ip.as_str()).collect::<Vec<_>>().join(",")),
+        "status.podIP" => status.and_then(|s| s.pod_ip.clone()),
+        "status.podIPs" => status.and_then(|s| s.pod_ips.as_ref())
+            .map(|ips| ips.iter().map(|ip| ip.ip.as_str()).collect::<Vec<_>>().join(",")),
+        _ => return Err(format!("unsupported fieldRef \"{}\"", path)),
+    };
+    Ok(value.unwrap_or_default())
+}
+
+/// `metadata.labels['app']` -> `app`
+fn map_key<'p>(path: &'p str, field: &str) -> Option<&'p str> {
+    path.strip_prefix(field)?
+        .strip_prefix("['")?
+        .strip_suffix("']")
+}
+
+/// Unset limits default to the node allocatable which isn't known here, unset requests are 0.
+fn resource_value(resources: Option<&ResourceRequirements>, resource: &str, divisor: Option<&str>) -> Result<String, String> {
+    let (section, name) = resource.split_once('.')
+        .ok_or_else(|| format!("unsupported resourceFieldRef \"{}\"", resource))?;
+    let values = match section {
+        "limits" => resources.and_then(|r| r.limits.as_ref()),
+        "requests" => resources.and_then(|r| r.requests.as_ref()),
+        _ => return Err(format!("unsupported resourceFieldRef \"{}\"", resource)),
+    };
+    if !matches!(name, "cpu" | "memory" | "ephemeral-storage") && !name.starts_with("hugepages-") {
+        return Err(format!("unsupported resourceFieldRef \"{}\"", resource));
+    }
+
+    let quantity = match values.and_then(|v| v.get(name)) {
+        Some(quantity) => quantity.0.as_str(),
+        None if section == "limits" => return Ok(format!("<node allocatable {}>", name)),
+        None => "0",
+    };
+    let amount = parse_quantity(quantity).ok_or_else(|| format!("invalid quantity \"{}\"", quantity))?;
+    let divisor = match divisor {
+        Some(divisor) => parse_quantity(divisor).filter(|d| *d > 0.0).ok_or_else(|| format!("invalid divisor \"{}\"", divisor))?,
+        None => 1.0,
+    };
+    // the kubelet rounds up, a 250m cpu limit is exposed as 1
+    Ok(format!("{}", (amount / divisor).ceil() as i64))
+}
+
+/// `500m`, `1.5`, `128Mi`, `1e3` to a plain number.
+fn parse_quantity(quantity: &str) -> Option<f64> {
+    let quantity = quantity.trim();
+    let split = quantity.find(|c: char| !(c.is_ascii_digit() || c == '.' || c == '+' || c == '-'))
+        .unwrap_or(quantity.len());
+    let (number, suffix) = quantity.split_at(split);
+    let number: f64 = number.parse().ok()?;
+    let multiplier = match suffix {
+        "" => 1.0,
+        "n" => 1e-9,
+        "u" => 1e-6,
+        "m" => 1e-3,
+        "k" => 1e3,
+        "M" => 1e6,
+        "G" => 1e9,
+        "T" => 1e12,
+        "P" => 1e15,
+        "E" => 1e18,
+        "Ki" => 1024f64,
+        "Mi" => 1024f64.powi(2),
+        "Gi" => 1024f64.powi(3),
+        "Ti" => 1024f64.powi(4),
+        "Pi" => 1024f64.powi(5),
+        "Ei" => 1024f64.powi(6),
+        exponent => 10f64.powi(exponent.strip_prefix(['e', 'E'])?.parse().ok()?),
+    };
+    Some(number * multiplier)
+}
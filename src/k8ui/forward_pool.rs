@@ -0,0 +1,293 @@
+//! Carries several forwarded connections of one pod port over a single portforward websocket.
+//!
+//! A portforward websocket (`v4.channel.k8s.io`) is requested for a list of ports and carries a data and an
+//! error channel per entry, the kubelet connects to the pod once for every entry. Listing the same port several
+//! times gives a session slots for that many connections. The protocol can't close a single channel, so a slot
+//! serves one connection and the websocket is closed once every slot is done.
+
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use anyhow::Context;
+use futures::future::{BoxFuture, Shared};
+use futures::{FutureExt, Sink, SinkExt, Stream, StreamExt, TryFutureExt};
+use k8s_openapi::api::core::v1::Pod;
+use kube::{Client, Resource};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, DuplexStream};
+use tokio::sync::mpsc;
+use tokio_tungstenite::tungstenite::{Error as WsError, Message};
+
+/// Connections sharing one websocket, the kubelet opens as many pod connections when the session starts.
+pub const SLOTS_PER_SESSION: usize = 8;
+
+/// Pod side data buffered per slot, a local client that stops reading stalls the whole websocket once this is full.
+const SLOT_BUFFER: usize = 256 * 1024;
+
+/// Pod frames queued for a slot before the websocket waits for it.
+const SLOT_FRAMES: usize = 16;
+
+const READ_CHUNK: usize = 32 * 1024;
+
+/// Pod data still reaches a connection until the pod was quiet this long after the connection stopped sending,
+/// the protocol can't tell when the pod is done.
+const LINGER: Duration = Duration::from_secs(10);
+
+/// Unused slots are pod connections nobody sent anything on, servers close those after a while.
+/// Slots are only handed out this long after the session started, the rest are closed then.
+const SLOT_MAX_AGE: Duration = Duration::from_secs(5);
+
+/// One portforward websocket and the local ends of its slots not handed out yet.
+pub struct Session {
+    opened: Instant,
+    free: Mutex<Vec<DuplexStream>>,
+}
+
+impl Session {
+    /// Runs the channel protocol over an already upgraded websocket that was requested for `port` `slots` times.
+    /// The task ends by itself when the websocket closes, or once every slot is done.
+    pub fn start<S>(ws: S, port: u16, slots: usize) -> Arc<Self>
+    where S: Stream<Item = Result<Message, WsError>> + Sink<Message, Error = WsError> + Send + Unpin + 'static {
+        let (free, remotes): (Vec<_>, Vec<_>) = (0..slots).map(|_| tokio::io::duplex(SLOT_BUFFER)).unzip();
+        let session = Arc::new(Self { opened: Instant::now(), free: Mutex::new(free) });
+        let task_session = session.clone();
+        tokio::spawn(async move {
+            if let Err(e) = message_loop(ws, port, remotes, &task_session).await {
+                println!("portforward session failed {:#}", e);
+            }
+            // a closed websocket has no use for its free slots
+            task_session.free.lock().unwrap().clear();
+        });
+        session
+    }
+
+    /// The local end of a slot, `None` when all are taken or the session is too old to hand out more.
+    pub fn take_slot(&self) -> Option<DuplexStream> {
+        if self.opened.elapsed() >= SLOT_MAX_AGE {
+            return None;
+        }
+        self.free.lock().unwrap().pop()
+    }
+}
+
+/// What the websocket received for a slot.
+enum SlotFrame {
+    Data(Vec<u8>),
+    Error(String),
+}
+
+async fn message_loop<S>(ws: S, port: u16, remotes: Vec<DuplexStream>, session: &Session) -> anyhow::Result<()>
+where S: Stream<Item = Result<Message, WsError>> + Sink<Message, Error = WsError> + Unpin {
+    let (mut sink, mut stream) = ws.split();
+    // every slot sends its frames, already carrying the channel, through here, it closes when all slots are done
+    let (frames_sender, mut frames) = mpsc::channel::<Vec<u8>>(SLOT_FRAMES);
+    let slots: Vec<_> = remotes.into_iter().enumerate()
+        .map(|(slot, remote)| {
+            let (sender, receiver) = mpsc::channel(SLOT_FRAMES);
+            tokio::spawn(slot_loop(slot as u8, remote, receiver, frames_sender.clone()));
+            sender
+        })
+        .collect();
+    drop(frames_sender);
+    // data and error channel of each slot start with a frame holding the port
+    let mut started = vec![false; slots.len() * 2];
+    let expire = tokio::time::sleep(SLOT_MAX_AGE);
+    tokio::pin!(expire);
+    let mut expired = false;
+    loop {
+        tokio::select! {
+            frame = frames.recv() => match frame {
+                Some(frame) => sink.send(Message::binary(frame)).await?,
+                None => break,
+            },
+            () = &mut expire, if !expired => {
+                // dropping the local end ends the slot
+                session.free.lock().unwrap().clear();
+                expired = true;
+            },
+            frame = stream.next() => match frame {
+                Some(Ok(Message::Binary(bytes))) => {
+                    let Some((&channel, mut data)) = bytes.split_first() else {
+                        continue;
+                    };
+                    let channel = channel as usize;
+                    let started = started.get_mut(channel).with_context(|| format!("frame on unknown channel {}", channel))?;
+                    if !*started {
+                        anyhow::ensure!(data.len() >= 2, "channel {} started without its port", channel);
+                        let frame_port = u16::from_le_bytes([data[0], data[1]]);
+                        anyhow::ensure!(frame_port == port, "channel {} is for port {}, expected {}", channel, frame_port, port);
+                        data = &data[2..];
+                        *started = true;
+                    }
+                    if data.is_empty() {
+                        continue;
+                    }
+                    let frame = match channel % 2 {
+                        0 => SlotFrame::Data(data.to_vec()),
+                        _ => SlotFrame::Error(String::from_utf8_lossy(data).into_owned()),
+                    };
+                    // a slot that is done drops the frames for it
+                    let _ = slots[channel / 2].send(frame).await;
+                }
+                Some(Ok(Message::Close(_))) | None => return Ok(()),
+                Some(Ok(_)) => {}
+                Some(Err(e)) => return Err(e.into()),
+            },
+        }
+    }
+    sink.send(Message::Close(None)).await?;
+    Ok(())
+}
+
+/// Moves the data of one connection between its local end and the websocket until both sides are done.
+async fn slot_loop(slot: u8, remote: DuplexStream, mut pod_frames: mpsc::Receiver<SlotFrame>, frames_sender: mpsc::Sender<Vec<u8>>) {
+    let (mut from_local, to_local) = tokio::io::split(remote);
+    let mut to_local = Some(to_local);
+    // buf[0] is the data channel of the slot
+    let mut buf = vec![0; READ_CHUNK];
+    buf[0] = slot * 2;
+    // the local side stopped sending, what the pod still sends is passed on until it was quiet long enough
+    let mut sending = true;
+    let linger = tokio::time::sleep(LINGER);
+    tokio::pin!(linger);
+    loop {
+        tokio::select! {
+            read = from_local.read(&mut buf[1..]), if sending => match read {
+                Ok(0) | Err(_) => {
+                    sending = false;
+                    linger.as_mut().reset(tokio::time::Instant::now() + LINGER);
+                }
+                Ok(n) => if frames_sender.send(buf[..n + 1].to_vec()).await.is_err() {
+                    return;
+                },
+            },
+            () = &mut linger, if !sending => {
+                if let Some(mut writer) = to_local.take() {
+                    let _ = writer.shutdown().await;
+                }
+            },
+            frame = pod_frames.recv() => match frame {
+                Some(SlotFrame::Data(data)) => {
+                    let Some(writer) = to_local.as_mut() else {
+                        continue;
+                    };
+                    if writer.write_all(&data).await.is_err() {
+                        // the local connection is gone, later data is dropped
+                        to_local = None;
+                    } else if !sending {
+                        // still answering
+                        linger.as_mut().reset(tokio::time::Instant::now() + LINGER);
+                    }
+                }
+                // the pod side failed or the websocket is gone
+                ended => {
+                    if let Some(SlotFrame::Error(error)) = ended {
+                        println!("portforward error in slot {}: {}", slot, error);
+                    }
+                    if let Some(mut writer) = to_local.take() {
+                        let _ = writer.shutdown().await;
+                    }
+                    sending = false;
+                }
+            },
+        }
+        if !sending && to_local.is_none() {
+            break;
+        }
+    }
+}
+
+/// Copies between a local connection and a slot stream until both directions are done.
+/// A side that stops sending is shut down on the other, a client that half closed still gets the response.
+pub async fn pipe_stream(mut stream: DuplexStream, mut client_conn: impl AsyncRead + AsyncWrite + Unpin) -> anyhow::Result<()> {
+    tokio::io::copy_bidirectional(&mut client_conn, &mut stream).await?;
+    println!("connection closed");
+    Ok(())
+}
+
+pub type OpenSession = Box<dyn Fn() -> BoxFuture<'static, anyhow::Result<Arc<Session>>> + Send + Sync>;
+
+/// A session being opened or open, shared by the connections that claimed one of its slots.
+type SharedSession = Shared<BoxFuture<'static, Result<Arc<Session>, Arc<anyhow::Error>>>>;
+
+/// Hands out slots of one pod port, a new session is opened when the current one has none left to give.
+/// Connections arriving while it is opened wait for it, nothing is opened ahead of a connection.
+pub struct SessionPool {
+    open: OpenSession,
+    current: Mutex<Option<Claims>>,
+}
+
+struct Claims {
+    session: SharedSession,
+    started: Instant,
+    claimed: usize,
+}
+
+impl Claims {
+    fn has_room(&self) -> bool {
+        let failed = matches!(self.session.peek(), Some(Err(_)));
+        !failed && self.claimed < SLOTS_PER_SESSION && self.started.elapsed() < SLOT_MAX_AGE
+    }
+}
+
+impl SessionPool {
+    pub fn new(open: OpenSession) -> Arc<Self> {
+        Arc::new(Self { open, current: Mutex::default() })
+    }
+
+    pub async fn take(&self) -> anyhow::Result<DuplexStream> {
+        let session = {
+            let mut current = self.current.lock().unwrap();
+            match current.as_mut().filter(|c| c.has_room()) {
+                Some(claims) => {
+                    claims.claimed += 1;
+                    claims.session.clone()
+                }
+                None => {
+                    // the old session closes by itself once its connections are done
+                    let session = (self.open)().map_err(Arc::new).boxed().shared();
+                    *current = Some(Claims { session: session.clone(), started: Instant::now(), claimed: 1 });
+                    session
+                }
+            }
+        };
+        let session = session.await.map_err(|e| anyhow::anyhow!("{:#}", e))?;
+        session.take_slot().context("portforward session has no slot left")
+    }
+}
+
+/// Session pools of one forward, a pool per pod and port so rerouted connections get sessions to the new pod.
+pub struct SessionPools {
+    client: Client,
+    namespace: String,
+    pools: Mutex<HashMap<(String, u16), Arc<SessionPool>>>,
+}
+
+impl SessionPools {
+    pub fn new(client: Client, namespace: String) -> Self {
+        Self { client, namespace, pools: Mutex::default() }
+    }
+
+    pub fn get(&self, pod_name: &str, port: u16) -> Arc<SessionPool> {
+        let mut pools = self.pools.lock().unwrap();
+        pools.entry((pod_name.to_owned(), port))
+            .or_insert_with(|| {
+                let (client, namespace, pod_name) = (self.client.clone(), self.namespace.clone(), pod_name.to_owned());
+                SessionPool::new(Box::new(move || {
+                    let (client, namespace, pod_name) = (client.clone(), namespace.clone(), pod_name.clone());
+                    Box::pin(async move {
+                        let request = portforward_request(&namespace, &pod_name, port, SLOTS_PER_SESSION)?;
+                        let ws = client.connect(request).await?;
+                        Ok(Session::start(ws, port, SLOTS_PER_SESSION))
+                    })
+                }))
+            })
+            .clone()
+    }
+}
+
+/// `Request::portforward` of kube rejects a port listed twice, the kubelet takes it as a channel pair per entry.
+fn portforward_request(namespace: &str, pod_name: &str, port: u16, slots: usize) -> anyhow::Result<http::Request<Vec<u8>>> {
+    let ports = vec![port.to_string(); slots].join(",");
+    let url = format!("{}/{}/portforward?ports={}", Pod::url_path(&(), Some(namespace)), pod_name, ports);
+    Ok(http::Request::get(url).body(vec![])?)
+}
@@ -0,0 +1,78 @@
+//! Byte counting for forwarded connections, counted on the local side of the forward.
+
+use std::io;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::task::{Context, Poll};
+use k8s_openapi::chrono::Utc;
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+use crate::k8ui::appstate::ForwardTraffic;
+
+/// Shared between a forward's listener and its connections, or owned by a single connection.
+#[derive(Default)]
+pub struct TrafficCounters {
+    /// Read from the local client, sent to the pod.
+    pub bytes_up: AtomicU64,
+    /// Received from the pod, written to the local client.
+    pub bytes_down: AtomicU64,
+    pub open: AtomicUsize,
+    pub accepted: AtomicU64,
+}
+
+impl TrafficCounters {
+    pub fn snapshot(&self) -> ForwardTraffic {
+        ForwardTraffic {
+            at: Utc::now(),
+            bytes_up: self.bytes_up.load(Ordering::Relaxed),
+            bytes_down: self.bytes_down.load(Ordering::Relaxed),
+            open: self.open.load(Ordering::Relaxed),
+            accepted: self.accepted.load(Ordering::Relaxed),
+        }
+    }
+}
+
+/// Counts what passes through into the connection's and the forward's counters.
+pub struct CountingStream<S> {
+    inner: S,
+    connection: Arc<TrafficCounters>,
+    forward: Arc<TrafficCounters>,
+}
+
+impl<S> CountingStream<S> {
+    pub fn new(inner: S, connection: Arc<TrafficCounters>, forward: Arc<TrafficCounters>) -> Self {
+        Self { inner, connection, forward }
+    }
+}
+
+impl<S: AsyncRead + Unpin> AsyncRead for CountingStream<S> {
+    fn poll_read(mut self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &mut ReadBuf<'_>) -> Poll<io::Result<()>> {
+        let before = buf.filled().len();
+        let poll = Pin::new(&mut self.inner).poll_read(cx, buf);
+        let read = (buf.filled().len() - before) as u64;
+        if read > 0 {
+            self.connection.bytes_up.fetch_add(read, Ordering::Relaxed);
+            self.forward.bytes_up.fetch_add(read, Ordering::Relaxed);
+        }
+        poll
+    }
+}
+
+impl<S: AsyncWrite + Unpin> AsyncWrite for CountingStream<S> {
+    fn poll_write(mut self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &[u8]) -> Poll<io::Result<usize>> {
+        let poll = Pin::new(&mut self.inner).poll_write(cx, buf);
+        if let Poll::Ready(Ok(written)) = poll {
+            self.connection.bytes_down.fetch_add(written as u64, Ordering::Relaxed);
+            self.forward.bytes_down.fetch_add(written as u64, Ordering::Relaxed);
+        }
+        poll
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_shutdown(cx)
+    }
+}
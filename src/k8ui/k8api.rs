@@ -1,220 +1,520 @@
 use std::collections::{HashMap};
-use std::convert::TryFrom;
-use std::error::Error;
-use std::net::SocketAddr;
+use std::env;
 use std::path::PathBuf;
-use base64::Engine;
-use base64::engine::general_purpose;
-use kube::{Api, Client, Config};
-use k8s_openapi::api::core::v1::{ConfigMap, Pod, Secret};
-use kube::api::{ListParams, LogParams};
+use kube::{Api, Client, Config, Resource};
+use k8s_openapi::api::core::v1::{ConfigMap, Container, ContainerPort, ContainerStateTerminated, ContainerStatus, EnvFromSource, EnvVar, EphemeralContainer, Namespace, Pod, Secret, Service};
+use kube::api::{ListParams, LogParams, Portforwarder};
 use kube::config::{Kubeconfig, KubeConfigOptions};
 use anyhow::Context;
-use k8s_openapi::api::apps::v1::Deployment;
-use k8s_openapi::chrono::Utc;
-use k8s_openapi::serde_json;
-use crate::k8ui::appstate::{ShortKContainer};
-use futures::{AsyncBufReadExt, TryStreamExt};
+use k8s_openapi::api::apps::v1::{DaemonSet, Deployment, ReplicaSet, StatefulSet};
+use k8s_openapi::api::batch::v1::{CronJob, Job};
+use k8s_openapi::apimachinery::pkg::apis::meta::v1::{ObjectMeta, OwnerReference};
+use k8s_openapi::apimachinery::pkg::util::intstr::IntOrString;
+use k8s_openapi::NamespaceResourceScope;
+use crate::k8ui::appstate::{ContainerKind, ContainerPortInfo, ContainerStateInfo, KubeconfigInfo, KubeconfigSource, KubeContextInfo, LogQuery, LogSince, PodConditionInfo, ServiceInfo, ServiceKey, ServicePortInfo, ShortKContainer, ShortKPod, Termination, WorkloadInfo, WorkloadKey};
 use tokio::io::{AsyncRead, AsyncWrite};
-use tokio::net::{TcpListener};
-use regex::Regex;
+use crate::k8ui::env_resolver::{EnvCache, EnvResolver};
+use crate::k8ui::selectors::{label_selector_string, match_labels_string};
 
 
+#[derive(Clone)]
 pub struct KubeApis {
-    // pub client: Client,
+    pub client: Client,
     pub api_pods: Api<Pod>,
-    pub api_cfm: Api<ConfigMap>,
-    pub api_secrets: Api<Secret>,
-    pub api_deployments: Api<Deployment>,
+    pub api_services: Api<Service>,
 }
 
+/// Owner chain lookups done so far, keyed by the direct owner, pods of one ReplicaSet share the answer.
+pub type OwnerCache = HashMap<WorkloadKey, WorkloadKey>;
 
-pub async fn refresh_client(conf_file_path: &str) -> Client {
-    let profile = Kubeconfig::read_from(PathBuf::from(conf_file_path)).unwrap();
-    let opts = KubeConfigOptions::default();
-    // Client::try_from(Config::from_kubeconfig(&opts).await.unwrap()).unwrap()
-    Client::try_from(Config::from_custom_kubeconfig(profile, &opts).await.unwrap()).unwrap()
+impl KubeApis {
+    /// Api for any namespaced kind, also in all-namespaces mode.
+    pub fn namespaced<K>(&self, namespace: &str) -> Api<K>
+    where
+        K: Resource<Scope = NamespaceResourceScope>,
+        <K as Resource>::DynamicType: Default,
+    {
+        Api::namespaced(self.client.clone(), namespace)
+    }
+
+    /// Api for a single pod. `api_pods` may be cluster wide and can't address one.
+    pub fn pods(&self, namespace: &str) -> Api<Pod> {
+        Api::namespaced(self.client.clone(), namespace)
+    }
+
+    pub fn config_maps(&self, namespace: &str) -> Api<ConfigMap> {
+        Api::namespaced(self.client.clone(), namespace)
+    }
+
+    pub fn secrets(&self, namespace: &str) -> Api<Secret> {
+        Api::namespaced(self.client.clone(), namespace)
+    }
 }
 
-pub async fn refresh_apis(client: &Client, namespace: &str) -> KubeApis {
-    let api_pods = Api::<Pod>::namespaced(client.clone(), namespace);
-    let api_cfm = Api::<ConfigMap>::namespaced(client.clone(), namespace);
-    let api_secrets = Api::<Secret>::namespaced(client.clone(), namespace);
-    // let api_services = Api::<Service>::namespaced(client.clone(), namespace);
-    let api_deployments = Api::<Deployment>::namespaced(client.clone(), namespace);
 
-    KubeApis {
-        api_pods,
-        api_cfm,
-        api_secrets,
-        api_deployments,
-    }
-}
-
-pub async fn refresh_clients(conf_file_path: &str, namespace: &str) -> Result<KubeApis, Box<dyn Error>> {
-    let profile = Kubeconfig::read_from(PathBuf::from(conf_file_path))?;
-    let opts = KubeConfigOptions::default();
-    println!("nu-ka");
-    let client = Client::try_from(Config::from_custom_kubeconfig(profile, &opts).await?)?;
-    let api_pods = Api::<Pod>::namespaced(client.clone(), namespace);
-    let api_cfm = Api::<ConfigMap>::namespaced(client.clone(), namespace);
-    let api_secrets = Api::<Secret>::namespaced(client.clone(), namespace);
-    // let api_services = Api::<Service>::namespaced(client.clone(), namespace);
-    let api_deployments = Api::<Deployment>::namespaced(client.clone(), namespace);
-
-
-    match api_pods.list(&ListParams::default()).await {
-        Ok(list) => {
-            let result: Vec<Pod> = list.into_iter()
-                .filter(|p| (&p.metadata.name).as_ref().unwrap().starts_with("quality"))
-                .collect();
-            println!("{:#?}", result.len());
+/// Kubeconfig files in the order kubectl would use them: every entry of `$KUBECONFIG`,
+/// or `~/.kube/config` when it is unset or empty.
+pub fn default_kubeconfig_paths() -> Vec<PathBuf> {
+    if let Some(value) = env::var_os("KUBECONFIG") {
+        let paths: Vec<PathBuf> = env::split_paths(&value)
+            .filter(|p| !p.as_os_str().is_empty())
+            .collect();
+        if !paths.is_empty() {
+            return paths;
         }
-        Err(e) => println!("{:?}", e),
-    };
-    Ok(KubeApis {
-        api_pods,
-        api_cfm,
-        api_secrets,
-        api_deployments,
-    })
+    }
+
+    env::var_os("HOME")
+        .or_else(|| env::var_os("USERPROFILE"))
+        .map(|home| vec![PathBuf::from(home).join(".kube").join("config")])
+        .unwrap_or_default()
 }
 
-//https://github.com/kube-rs/kube/blob/main/examples/configmapgen_controller.rs
-pub async fn refresh_pod_list(prefix: &str, api_pods: &Api<Pod>, api_cfm: &Api<ConfigMap>, api_secrets: &Api<Secret>) -> Result<Vec<ShortKContainer>, Box<dyn Error>> {
-    let mut refreshed_pods = Vec::new();
+pub fn split_kubeconfig_paths(paths: &str) -> Vec<PathBuf> {
+    env::split_paths(paths)
+        .filter(|p| !p.as_os_str().is_empty())
+        .collect()
+}
 
-    let list: Vec<Pod> = api_pods.list(&ListParams::default()).await?.into_iter()
-        .filter(|p| (&p.metadata.name).as_ref().unwrap().starts_with(prefix))
-        .collect();
+pub fn join_kubeconfig_paths(paths: &[PathBuf]) -> String {
+    env::join_paths(paths)
+        .map(|joined| joined.to_string_lossy().into_owned())
+        .unwrap_or_default()
+}
 
-    for x in &list {        //https://users.rust-lang.org/t/nested-match-hell-in-rust/57628/4
-        let spec = x.spec.as_ref().unwrap();
-        let container = &spec.containers[0];
-        let start = x.metadata.creation_timestamp.as_ref().unwrap().0;
-        let now = Utc::now();
-        let diff = now - start;
-
-        let pod_name = x.metadata.name.clone().unwrap();
-        let age = format!("{}d, {}h, {}m", diff.num_days(), diff.num_hours() - diff.num_days() * 24, diff.num_minutes() - diff.num_hours() * 60);
-        let image = container.image.as_ref().unwrap().to_owned();
-
-        let mut restarts = 0u32;
-        let mut status = "";
-        if let Some(data) = &x.status {
-            if let Some(statuses) = &data.container_statuses {
-                let s = statuses.get(0).unwrap();
-                restarts = s.restart_count as u32;
-                if let Some(state) = &s.state {
-                    if state.running.is_some() {
-                        status = "Running";
-                    }
+/// Reads and merges `paths` with the kubectl rules: the first file to define a name wins.
+/// Missing or broken files are reported in their source entry and skipped.
+pub fn read_kubeconfigs(paths: &[PathBuf]) -> (Option<Kubeconfig>, KubeconfigInfo) {
+    let mut merged: Option<Kubeconfig> = None;
+    let mut sources = Vec::new();
+    let mut context_sources: HashMap<String, PathBuf> = HashMap::new();
 
-                    if state.waiting.is_some() {
-                        status = "Waiting";
-                    }
+    for path in paths {
+        let mut source = KubeconfigSource {
+            path: path.clone(),
+            contexts: vec![],
+            clusters: vec![],
+            users: vec![],
+            error: None,
+        };
 
-                    if state.terminated.is_some() {
-                        status = "Terminated";
+        match Kubeconfig::read_from(path) {
+            Ok(config) => {
+                let known = merged.clone().unwrap_or_default();
+                match known.clone().merge(config.clone()) {
+                    Ok(next) => {
+                        source.contexts = new_names(&config.contexts, &known.contexts, |c| &c.name);
+                        source.clusters = new_names(&config.clusters, &known.clusters, |c| &c.name);
+                        source.users = new_names(&config.auth_infos, &known.auth_infos, |c| &c.name);
+                        for name in source.contexts.iter() {
+                            context_sources.insert(name.clone(), path.clone());
+                        }
+                        merged = Some(next);
                     }
+                    Err(e) => source.error = Some(e.to_string()),
                 }
             }
+            Err(e) => source.error = Some(e.to_string()),
         }
 
-        let mut ports = HashMap::new();
-        if let Some(data) = container.ports.as_ref() {
-            ports = data.iter()
-                .map(|p| { (p.protocol.clone().unwrap(), p.container_port as u16) })
-                .collect::<HashMap<String, u16>>();
-        }
+        sources.push(source);
+    }
 
+    let contexts = merged.as_ref()
+        .map(list_contexts)
+        .unwrap_or_default()
+        .into_iter()
+        .map(|mut context| {
+            context.source = context_sources.get(&context.name).cloned();
+            context
+        })
+        .collect();
+    let info = KubeconfigInfo {
+        sources,
+        contexts,
+        current_context: merged.as_ref().and_then(|k| k.current_context.clone()),
+    };
 
-        let mut cfm = HashMap::new();
-        let mut sm = HashMap::new();
-        if let Some(envs) = container.env_from.as_ref() {
-            for ee in envs {
-                if let Some(confmap) = &ee.config_map_ref {
-                    let name = confmap.name.as_ref();
-                    if let Some(data) = api_cfm.get(name).await.unwrap().data {
-                        cfm = data.into_iter()
-                            .map(|e| (e.0, e.1))
-                            .collect::<HashMap<String, String>>();
-                    }
-                }
-                if let Some(secrets) = &ee.secret_ref {
-                    let name = secrets.name.as_ref();
-                    if let Some(data) = api_secrets.get(name).await.unwrap().data {
-                        sm = data.into_iter()
-                            .map(|e| {
-                                let key = e.0;
-                                let mut val = serde_json::to_string(&e.1).unwrap().replace("\"", "");
-                                val = String::from_utf8(general_purpose::STANDARD.decode(val).unwrap()).unwrap();
-                                (key, val)
-                            })
-                            .collect::<HashMap<String, String>>();
-                    }
-                }
+    (merged, info)
+}
+
+fn new_names<T>(next: &[T], known: &[T], name: impl Fn(&T) -> &String) -> Vec<String> {
+    next.iter()
+        .map(&name)
+        .filter(|n| !known.iter().any(|k| name(k) == *n))
+        .cloned()
+        .collect()
+}
+
+pub fn list_contexts(kubeconfig: &Kubeconfig) -> Vec<KubeContextInfo> {
+    kubeconfig.contexts.iter()
+        .map(|named| {
+            let context = named.context.as_ref();
+            KubeContextInfo {
+                name: named.name.clone(),
+                cluster: context.map(|c| c.cluster.clone()).unwrap_or_default(),
+                user: context.and_then(|c| c.user.clone()).unwrap_or_default(),
+                namespace: context.and_then(|c| c.namespace.clone()),
+                source: None,
             }
+        })
+        .collect()
+}
+
+/// Builds a client for `context`, or for the kubeconfig's `current-context` when `None`.
+pub async fn refresh_client(kubeconfig: Kubeconfig, context: Option<String>) -> anyhow::Result<Client> {
+    let opts = KubeConfigOptions {
+        context,
+        ..KubeConfigOptions::default()
+    };
+    let config = Config::from_custom_kubeconfig(kubeconfig, &opts).await?;
+    Ok(Client::try_from(config)?)
+}
+
+/// Builds the apis for `namespace`, or cluster wide ones (`Api::all`) when it is `None`.
+pub async fn refresh_apis(client: &Client, namespace: Option<&str>) -> KubeApis {
+    let (api_pods, api_services) = match namespace {
+        Some(namespace) => (
+            Api::<Pod>::namespaced(client.clone(), namespace),
+            Api::<Service>::namespaced(client.clone(), namespace),
+        ),
+        None => (Api::<Pod>::all(client.clone()), Api::<Service>::all(client.clone())),
+    };
+
+    KubeApis {
+        client: client.clone(),
+        api_pods,
+        api_services,
+    }
+}
+
+pub async fn list_namespaces(client: &Client) -> anyhow::Result<Vec<String>> {
+    let api_namespaces = Api::<Namespace>::all(client.clone());
+    let mut namespaces: Vec<String> = api_namespaces.list(&ListParams::default()).await?.into_iter()
+        .filter_map(|ns| ns.metadata.name)
+        .collect();
+    namespaces.sort();
+    Ok(namespaces)
+}
+
+/// The parts of `Container` and `EphemeralContainer` shown in a column.
+struct ContainerView<'a> {
+    name: &'a str,
+    kind: ContainerKind,
+    image: Option<&'a String>,
+    ports: Option<&'a Vec<ContainerPort>>,
+    env: Option<&'a Vec<EnvVar>>,
+    env_from: Option<&'a Vec<EnvFromSource>>,
+}
+
+impl<'a> ContainerView<'a> {
+    fn of(container: &'a Container, kind: ContainerKind) -> Self {
+        Self {
+            name: container.name.as_str(),
+            kind,
+            image: container.image.as_ref(),
+            ports: container.ports.as_ref(),
+            env: container.env.as_ref(),
+            env_from: container.env_from.as_ref(),
+        }
+    }
+
+    fn of_ephemeral(container: &'a EphemeralContainer) -> Self {
+        Self {
+            name: container.name.as_str(),
+            kind: ContainerKind::Ephemeral,
+            image: container.image.as_ref(),
+            ports: container.ports.as_ref(),
+            env: container.env.as_ref(),
+            env_from: container.env_from.as_ref(),
+        }
+    }
+}
+
+fn controller_of(meta: &ObjectMeta) -> Option<&OwnerReference> {
+    let owners = meta.owner_references.as_ref()?;
+    owners.iter()
+        .find(|o| o.controller == Some(true))
+        .or_else(|| owners.first())
+}
+
+/// Follows Pod -> ReplicaSet -> Deployment and Pod -> Job -> CronJob, other controllers are returned as is.
+pub async fn resolve_owner(pod: &Pod, apis: &KubeApis, cache: &mut OwnerCache) -> Option<WorkloadKey> {
+    let owner = controller_of(&pod.metadata)?;
+    let direct = WorkloadKey {
+        namespace: pod.metadata.namespace.clone().unwrap_or_default(),
+        kind: owner.kind.clone(),
+        name: owner.name.clone(),
+    };
+
+    let parent_kind = match direct.kind.as_str() {
+        "ReplicaSet" => "Deployment",
+        "Job" => "CronJob",
+        _ => return Some(direct),
+    };
+    if let Some(resolved) = cache.get(&direct) {
+        return Some(resolved.clone());
+    }
+
+    let metadata = match direct.kind.as_str() {
+        "ReplicaSet" => apis.namespaced::<ReplicaSet>(&direct.namespace).get_metadata_opt(&direct.name).await
+            .map(|o| o.map(|r| r.metadata)),
+        _ => apis.namespaced::<Job>(&direct.namespace).get_metadata_opt(&direct.name).await
+            .map(|o| o.map(|j| j.metadata)),
+    };
+    let resolved = match metadata {
+        Ok(Some(metadata)) => controller_of(&metadata)
+            .filter(|o| o.kind == parent_kind)
+            .map(|o| WorkloadKey {
+                namespace: direct.namespace.clone(),
+                kind: o.kind.clone(),
+                name: o.name.clone(),
+            })
+            .unwrap_or_else(|| direct.clone()),
+        Ok(None) => direct.clone(),
+        Err(e) => {
+            println!("Error resolving owner of {} {}", direct, e);
+            direct.clone()
         }
+    };
 
-        let c = ShortKContainer::new(pod_name, age, image, status.to_owned(), restarts, ports, cfm, sm);
-        println!("{:#?}",&c);
+    cache.insert(direct, resolved.clone());
+    Some(resolved)
+}
 
-        refreshed_pods.push(c);
+pub async fn workload_info(key: &WorkloadKey, apis: &KubeApis) -> anyhow::Result<WorkloadInfo> {
+    let mut info = WorkloadInfo::new(key.clone());
+    let (namespace, name) = (key.namespace.as_str(), key.name.as_str());
+    match key.kind.as_str() {
+        "Deployment" => {
+            let deployment = apis.namespaced::<Deployment>(namespace).get(name).await?;
+            let status = deployment.status.unwrap_or_default();
+            info.desired = Some(deployment.spec.and_then(|s| s.replicas).unwrap_or(1));
+            info.ready = Some(status.ready_replicas.unwrap_or_default());
+            info.updated = Some(status.updated_replicas.unwrap_or_default());
+        }
+        "StatefulSet" => {
+            let stateful_set = apis.namespaced::<StatefulSet>(namespace).get(name).await?;
+            let status = stateful_set.status.unwrap_or_default();
+            info.desired = Some(stateful_set.spec.and_then(|s| s.replicas).unwrap_or(1));
+            info.ready = Some(status.ready_replicas.unwrap_or_default());
+            info.updated = Some(status.updated_replicas.unwrap_or_default());
+        }
+        "DaemonSet" => {
+            let status = apis.namespaced::<DaemonSet>(namespace).get(name).await?.status.unwrap_or_default();
+            info.desired = Some(status.desired_number_scheduled);
+            info.ready = Some(status.number_ready);
+            info.updated = Some(status.updated_number_scheduled.unwrap_or_default());
+        }
+        "ReplicaSet" => {
+            let replica_set = apis.namespaced::<ReplicaSet>(namespace).get(name).await?;
+            info.desired = Some(replica_set.spec.and_then(|s| s.replicas).unwrap_or(1));
+            info.ready = Some(replica_set.status.and_then(|s| s.ready_replicas).unwrap_or_default());
+        }
+        "Job" => {
+            let job = apis.namespaced::<Job>(namespace).get(name).await?;
+            let status = job.status.unwrap_or_default();
+            info.desired = job.spec.and_then(|s| s.completions);
+            info.active = Some(status.active.unwrap_or_default());
+            info.succeeded = Some(status.succeeded.unwrap_or_default());
+        }
+        "CronJob" => {
+            let cron_job = apis.namespaced::<CronJob>(namespace).get(name).await?;
+            info.active = Some(cron_job.status.and_then(|s| s.active).map(|a| a.len() as i32).unwrap_or_default());
+        }
+        _ => {}
     }
+    Ok(info)
+}
 
-    Ok(refreshed_pods)
+/// Label selector of the pods a workload manages, CronJobs have none, their pods belong to Jobs.
+pub async fn workload_selector(key: &WorkloadKey, apis: &KubeApis) -> anyhow::Result<String> {
+    let (namespace, name) = (key.namespace.as_str(), key.name.as_str());
+    let selector = match key.kind.as_str() {
+        "Deployment" => apis.namespaced::<Deployment>(namespace).get(name).await?.spec.map(|s| s.selector),
+        "StatefulSet" => apis.namespaced::<StatefulSet>(namespace).get(name).await?.spec.map(|s| s.selector),
+        "DaemonSet" => apis.namespaced::<DaemonSet>(namespace).get(name).await?.spec.map(|s| s.selector),
+        "ReplicaSet" => apis.namespaced::<ReplicaSet>(namespace).get(name).await?.spec.map(|s| s.selector),
+        "Job" => apis.namespaced::<Job>(namespace).get(name).await?.spec.and_then(|s| s.selector),
+        kind => anyhow::bail!("{} has no pod selector", kind),
+    };
+    let selector = selector.map(|s| label_selector_string(&s)).unwrap_or_default();
+    anyhow::ensure!(!selector.is_empty(), "{} has an empty selector", key);
+    Ok(selector)
 }
 
-pub async fn logs(pod_name: String, api_pods: &Api<Pod>) -> anyhow::Result<Vec<String>> {
-    let mut logs = api_pods
-        .log_stream(pod_name.as_str(), &LogParams {
-            follow: false,
-            tail_lines: Some(100),
-            timestamps: false,
-            ..LogParams::default()
+pub async fn list_services(apis: &KubeApis) -> anyhow::Result<Vec<ServiceInfo>> {
+    let mut services: Vec<ServiceInfo> = apis.api_services.list(&ListParams::default()).await?.iter()
+        .map(to_service_info)
+        .collect();
+    services.sort_by(|a, b| a.key.cmp(&b.key));
+    Ok(services)
+}
+
+pub fn to_service_info(service: &Service) -> ServiceInfo {
+    let spec = service.spec.clone().unwrap_or_default();
+    let ports = spec.ports.iter().flatten()
+        .map(|p| ServicePortInfo {
+            name: p.name.clone().filter(|n| !n.is_empty()),
+            protocol: p.protocol.clone().unwrap_or_else(|| "TCP".to_owned()),
+            port: p.port as u16,
+            target_port: match &p.target_port {
+                Some(IntOrString::Int(port)) => port.to_string(),
+                Some(IntOrString::String(name)) => name.clone(),
+                // defaults to the service port
+                None => p.port.to_string(),
+            },
         })
-        .await?
-        .lines();
+        .collect();
+    ServiceInfo {
+        key: ServiceKey {
+            namespace: service.metadata.namespace.clone().unwrap_or_default(),
+            name: service.metadata.name.clone().unwrap_or_default(),
+        },
+        service_type: spec.type_.unwrap_or_else(|| "ClusterIP".to_owned()),
+        cluster_ip: spec.cluster_ip,
+        selector: spec.selector.as_ref().map(match_labels_string).filter(|s| !s.is_empty()),
+        ports,
+    }
+}
+
+/// Running, not terminating and passing its readiness checks.
+pub fn is_pod_ready(pod: &Pod) -> bool {
+    let status = pod.status.as_ref();
+    pod.metadata.deletion_timestamp.is_none()
+        && status.and_then(|s| s.phase.as_deref()) == Some("Running")
+        && status.and_then(|s| s.conditions.as_ref())
+            .is_some_and(|conditions| conditions.iter().any(|c| c.type_ == "Ready" && c.status == "True"))
+}
+
+//https://github.com/kube-rs/kube/blob/main/examples/configmapgen_controller.rs
+pub async fn pod_to_short(x: &Pod, owner: Option<WorkloadKey>, apis: &KubeApis, env_cache: &mut EnvCache) -> anyhow::Result<ShortKPod> {
+    //https://users.rust-lang.org/t/nested-match-hell-in-rust/57628/4
+    let spec = x.spec.as_ref().context("pod without spec")?;
+    let created = x.metadata.creation_timestamp.as_ref().map(|t| t.0);
+    let pod_name = x.metadata.name.clone().unwrap_or_default();
+    let namespace = x.metadata.namespace.clone().unwrap_or_default();
+
+    let views = spec.init_containers.iter().flatten().map(|c| ContainerView::of(c, ContainerKind::Init))
+        .chain(spec.containers.iter().map(|c| ContainerView::of(c, ContainerKind::Regular)))
+        .chain(spec.ephemeral_containers.iter().flatten().map(ContainerView::of_ephemeral));
+
+    let mut env = EnvResolver::new(x, apis, env_cache);
+    let mut containers = Vec::new();
+    for view in views {
+        let statuses = x.status.as_ref().and_then(|s| match view.kind {
+            ContainerKind::Init => s.init_container_statuses.as_ref(),
+            ContainerKind::Regular => s.container_statuses.as_ref(),
+            ContainerKind::Ephemeral => s.ephemeral_container_statuses.as_ref(),
+        });
+        let status = statuses.and_then(|all| all.iter().find(|s| s.name == view.name));
+        containers.push(to_short_container(&view, status, &mut env).await);
+    }
+
+    let phase = x.status.as_ref().and_then(|s| s.phase.clone()).unwrap_or_default();
+    let conditions = x.status.as_ref()
+        .and_then(|s| s.conditions.as_ref())
+        .map(|conditions| conditions.iter()
+            .map(|c| PodConditionInfo {
+                condition_type: c.type_.clone(),
+                status: c.status.clone(),
+                reason: c.reason.clone(),
+                message: c.message.clone(),
+                last_transition: c.last_transition_time.as_ref().map(|t| t.0),
+            })
+            .collect())
+        .unwrap_or_default();
+
+    Ok(ShortKPod::new(pod_name, namespace, created, phase, conditions, owner, containers))
+}
 
-    println!("lines");
+fn to_state_info(status: &ContainerStatus) -> ContainerStateInfo {
+    let mut info = ContainerStateInfo {
+        ready: status.ready,
+        started: status.started,
+        last_termination: status.last_state.as_ref()
+            .and_then(|s| s.terminated.as_ref())
+            .map(to_termination),
+        ..ContainerStateInfo::default()
+    };
+
+    if let Some(state) = &status.state {
+        if let Some(running) = &state.running {
+            info.state = "Running".to_owned();
+            info.started_at = running.started_at.as_ref().map(|t| t.0);
+        }
 
-    let regex = Regex::new(r"\u001B\[\d*m").unwrap();
-    let mut result = vec![];
-    while let Some(mut line) = logs.try_next().await? { //if follow
-        let fixed_str = regex.replace_all(line.as_str(), "").into_owned();
-        result.push(fixed_str);
+        if let Some(waiting) = &state.waiting {
+            info.state = "Waiting".to_owned();
+            info.reason = waiting.reason.clone();
+            info.message = waiting.message.clone();
+        }
+
+        if let Some(terminated) = &state.terminated {
+            info.state = "Terminated".to_owned();
+            info.reason = terminated.reason.clone();
+            info.message = terminated.message.clone();
+            info.started_at = terminated.started_at.as_ref().map(|t| t.0);
+            info.termination = Some(to_termination(terminated));
+        }
     }
-    println!("ok... {}", result.len());
-    Ok(result)
+    info
 }
 
-pub async fn forward_connection(api_pods: Api<Pod>, pod_name: &str, port: u16,
-                                mut client_conn: impl AsyncRead + AsyncWrite + Unpin, ) -> anyhow::Result<()> {
-    let mut forwarder = api_pods.portforward(pod_name, &[port]).await?;
-    let mut upstream_conn = forwarder.take_stream(port).context("port not found in forwarder")?;
-    tokio::io::copy_bidirectional(&mut client_conn, &mut upstream_conn).await?;
-    drop(upstream_conn);//reuse connection?
-    forwarder.join().await?;
-    println!("connection closed");
-    Ok(())
+fn to_termination(terminated: &ContainerStateTerminated) -> Termination {
+    Termination {
+        reason: terminated.reason.clone(),
+        message: terminated.message.clone(),
+        exit_code: terminated.exit_code,
+        signal: terminated.signal,
+        finished_at: terminated.finished_at.as_ref().map(|t| t.0),
+    }
 }
 
+async fn to_short_container(container: &ContainerView<'_>, container_status: Option<&ContainerStatus>, env: &mut EnvResolver<'_>) -> ShortKContainer {
+    let image = container.image.cloned().unwrap_or_default();
 
-pub async fn forward_connection2(api_pods: Api<Pod>, pod_name: &str, port: u16) -> anyhow::Result<()> {
-    let mut forwarder = api_pods.portforward(pod_name, &[port]).await?;
-    let mut upstream_conn = forwarder.take_stream(port).context("port not found in forwarder")?;
+    let restarts = container_status.map(|s| s.restart_count as u32).unwrap_or_default();
+    let status = container_status.map(to_state_info).unwrap_or_default();
+
+    let ports = container.ports.into_iter().flatten()
+        .map(|p| ContainerPortInfo {
+            name: p.name.clone(),
+            protocol: p.protocol.clone().unwrap_or_else(|| "TCP".to_owned()),
+            number: p.container_port as u16,
+        })
+        .collect();
+
+    let env = env.resolve(container.name, container.env, container.env_from).await;
 
-    let addr = SocketAddr::from(([127, 0, 0, 1], port + 1));
-    let listener = TcpListener::bind(addr).await?;
-    let (mut socket, _) = listener.accept().await?;
-    println!("spawn bidir copy");
+    ShortKContainer::new(container.name.to_owned(), container.kind, image, status, restarts, ports, env)
+}
 
-    tokio::spawn(async move {
-        tokio::io::copy_bidirectional(&mut socket, &mut upstream_conn).await.unwrap();
-        println!("some task cant get here");
-    });
+pub fn log_params(query: &LogQuery, follow: bool) -> LogParams {
+    let (since_seconds, since_time) = match query.since {
+        Some(LogSince::Seconds(seconds)) => (Some(seconds), None),
+        Some(LogSince::Time(time)) => (None, Some(time)),
+        None => (None, None),
+    };
+    LogParams {
+        container: query.container.clone(),
+        follow,
+        limit_bytes: query.limit_bytes,
+        previous: query.previous,
+        since_seconds,
+        since_time,
+        tail_lines: query.tail_lines,
+        timestamps: query.timestamps,
+        ..LogParams::default()
+    }
+}
 
+/// Copies between the local connection and an already opened portforward until either side closes.
+pub async fn pipe_forwarded(mut forwarder: Portforwarder, port: u16,
+                            mut client_conn: impl AsyncRead + AsyncWrite + Unpin, ) -> anyhow::Result<()> {
+    let mut upstream_conn = forwarder.take_stream(port).context("port not found in forwarder")?;
+    tokio::io::copy_bidirectional(&mut client_conn, &mut upstream_conn).await?;
+    drop(upstream_conn);//reuse connection?
     forwarder.join().await?;
     println!("connection closed");
     Ok(())
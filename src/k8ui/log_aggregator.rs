@@ -0,0 +1,206 @@
+//! Tails every container of a set of pods into one stream, like `stern`.
+
+use std::collections::{BTreeSet, HashMap};
+use std::sync::Arc;
+use crossbeam::channel::Sender;
+use futures::{AsyncBufReadExt, StreamExt, TryStreamExt};
+use k8s_openapi::api::core::v1::Pod;
+use k8s_openapi::chrono::{DateTime, Utc};
+use kube::Api;
+use kube::api::ListParams;
+use kube::runtime::{watcher, WatchStreamExt};
+use tokio::sync::mpsc;
+use tokio::task::{AbortHandle, JoinSet};
+use crate::k8ui::appstate::{LogLine, LogRequest, LogSince, LogSource, LogTarget, PodKey, PodQuery};
+use crate::k8ui::k8api::{log_params, workload_selector, KubeApis};
+use crate::k8ui::log_streamer::{split_timestamp, BATCH_INTERVAL};
+use crate::k8ui::my_thread::{send_data, ThreadMessage, UIData};
+use crate::k8ui::pod_watcher::watcher_config;
+
+/// Runs until dropped when following, otherwise until the logs of the pods listed at the start are read.
+pub async fn aggregate(apis: &KubeApis, request: &LogRequest, ui_sender: &Sender<ThreadMessage>) -> anyhow::Result<()> {
+    let (api_pods, query) = match &request.target {
+        LogTarget::Pod(pod) => anyhow::bail!("{} is a single pod, its log is streamed as is", pod),
+        LogTarget::Pods(query) => (apis.api_pods.clone(), query.clone()),
+        LogTarget::Workload(workload) => {
+            let label_selector = workload_selector(workload, apis).await?;
+            (apis.pods(workload.namespace.as_str()), PodQuery { label_selector, ..PodQuery::default() })
+        }
+    };
+    let matcher = query.name_matcher().map_err(anyhow::Error::msg)?;
+    let mut tails = Tails::new(apis.clone(), request.clone());
+    let mut flush = tokio::time::interval(BATCH_INTERVAL);
+
+    if !request.follow {
+        let config = watcher_config(&query);
+        let params = ListParams { label_selector: config.label_selector, field_selector: config.field_selector, ..ListParams::default() };
+        for pod in api_pods.list(&params).await?.items.iter().filter(|p| matcher.matches(pod_name(p))) {
+            tails.attach(pod);
+        }
+        tails.send_sources(ui_sender);
+        while !tails.streams.is_empty() {
+            tokio::select! {
+                Some(line) = tails.lines.recv() => tails.receive(line),
+                Some(ended) = tails.streams.join_next() => tails.detach(ended, ui_sender),
+                _ = flush.tick() => tails.flush(ui_sender),
+            }
+        }
+        // streams are done, whatever they sent is queued already
+        while let Ok(line) = tails.lines.try_recv() {
+            tails.receive(line);
+        }
+        tails.flush(ui_sender);
+        return Ok(());
+    }
+
+    // attaches to the pods there at the start and to every one that gets a running container later
+    let mut pods = watcher(api_pods, watcher_config(&query))
+        .default_backoff()
+        .applied_objects()
+        .boxed();
+    loop {
+        tokio::select! {
+            pod = pods.try_next() => match pod? {
+                Some(pod) => if matcher.matches(pod_name(&pod)) && tails.attach(&pod) {
+                    tails.send_sources(ui_sender);
+                },
+                None => break,
+            },
+            Some(line) = tails.lines.recv() => tails.receive(line),
+            Some(ended) = tails.streams.join_next(), if !tails.streams.is_empty() => tails.detach(ended, ui_sender),
+            _ = flush.tick() => tails.flush(ui_sender),
+        }
+    }
+    tails.flush(ui_sender);
+    Ok(())
+}
+
+fn pod_name(pod: &Pod) -> &str {
+    pod.metadata.name.as_deref().unwrap_or_default()
+}
+
+/// The container streams of one aggregated request.
+struct Tails {
+    /// Logs are read through the api of each pod's namespace, the listing one may be cluster wide.
+    apis: KubeApis,
+    request: LogRequest,
+    streams: JoinSet<(Arc<LogSource>, anyhow::Result<()>)>,
+    attached: HashMap<Arc<LogSource>, AbortHandle>,
+    /// Newest line per source, a container attached again continues after it instead of repeating its tail.
+    last_seen: HashMap<Arc<LogSource>, DateTime<Utc>>,
+    lines_sender: mpsc::UnboundedSender<LogLine>,
+    lines: mpsc::UnboundedReceiver<LogLine>,
+    batch: Vec<LogLine>,
+}
+
+impl Tails {
+    fn new(apis: KubeApis, request: LogRequest) -> Self {
+        let (lines_sender, lines) = mpsc::unbounded_channel();
+        Self {
+            apis,
+            request,
+            streams: JoinSet::new(),
+            attached: HashMap::new(),
+            last_seen: HashMap::new(),
+            lines_sender,
+            lines,
+            batch: Vec::new(),
+        }
+    }
+
+    /// Starts streams for the containers of the pod that aren't streamed yet, true when one was started.
+    /// Following takes the running containers, a one time read also the terminated ones, e.g. of finished jobs.
+    fn attach(&mut self, pod: &Pod) -> bool {
+        let key = PodKey::new(pod.metadata.namespace.clone().unwrap_or_default(), pod_name(pod).to_owned());
+        let status = pod.status.as_ref();
+        let follow = self.request.follow;
+        let readable = status.iter()
+            .flat_map(|s| s.init_container_statuses.iter().flatten().chain(s.container_statuses.iter().flatten()))
+            .filter(|c| c.state.as_ref().is_some_and(|s| s.running.is_some() || (!follow && s.terminated.is_some())))
+            .filter(|c| self.request.query.container.as_ref().is_none_or(|name| *name == c.name));
+
+        let mut started = false;
+        for container in readable {
+            let source = Arc::new(LogSource { pod: key.clone(), container: container.name.clone() });
+            if self.attached.contains_key(&source) {
+                continue;
+            }
+
+            let mut query = self.request.query.clone();
+            query.container = Some(source.container.clone());
+            query.previous = false;
+            if let Some(seen) = self.last_seen.get(&source) {
+                query.tail_lines = None;
+                query.since = Some(LogSince::Time(*seen));
+            }
+            let mut params = log_params(&query, self.request.follow);
+            // needed to interleave the pods, cut off again unless asked for
+            params.timestamps = true;
+
+            let stream = tail(self.apis.pods(&source.pod.namespace), source.clone(), params, self.request.query.timestamps, self.last_seen.get(&source).copied(), self.lines_sender.clone());
+            let task_source = source.clone();
+            let handle = self.streams.spawn(async move { (task_source, stream.await) });
+            self.attached.insert(source, handle);
+            started = true;
+        }
+        started
+    }
+
+    fn detach(&mut self, ended: Result<(Arc<LogSource>, anyhow::Result<()>), tokio::task::JoinError>, ui_sender: &Sender<ThreadMessage>) {
+        let Ok((source, result)) = ended else {
+            return;
+        };
+        if let Err(e) = result {
+            println!("log stream of {} failed {:#}", source, e);
+        }
+        self.attached.remove(&source);
+        self.send_sources(ui_sender);
+    }
+
+    fn receive(&mut self, line: LogLine) {
+        if let (Some(at), Some(source)) = (line.at, &line.source) {
+            self.last_seen.insert(source.clone(), at);
+        }
+        self.batch.push(line);
+    }
+
+    /// Sends the batch in time order, the window merges it with what it already has.
+    fn flush(&mut self, ui_sender: &Sender<ThreadMessage>) {
+        if self.batch.is_empty() {
+            return;
+        }
+        let mut batch = std::mem::take(&mut self.batch);
+        batch.sort_by_key(|line| line.at);
+        send_data(ui_sender, UIData::Logs(self.request.id, batch));
+    }
+
+    fn send_sources(&self, ui_sender: &Sender<ThreadMessage>) {
+        let sources: BTreeSet<LogSource> = self.attached.keys().map(|s| LogSource::clone(s)).collect();
+        send_data(ui_sender, UIData::LogSources(self.request.id, sources.into_iter().collect()));
+    }
+}
+
+async fn tail(api_pods: Api<Pod>, source: Arc<LogSource>, params: kube::api::LogParams, keep_timestamps: bool,
+              seen: Option<DateTime<Utc>>, lines_sender: mpsc::UnboundedSender<LogLine>) -> anyhow::Result<()> {
+    let mut lines = api_pods.log_stream(source.pod.name.as_str(), &params).await?.lines();
+    while let Some(line) = lines.try_next().await? {
+        let (at, text) = split_timestamp(&line);
+        // since is inclusive, the line seen last comes again
+        if at.is_some() && at <= seen {
+            continue;
+        }
+        let text = if keep_timestamps { line.as_str() } else { text };
+        if lines_sender.send(LogLine::parse(at, Some(source.clone()), text)).is_err() {
+            break;
+        }
+    }
+    Ok(())
+}
+
+impl Drop for Tails {
+    fn drop(&mut self) {
+        for handle in self.attached.values() {
+            handle.abort();
+        }
+    }
+}
@@ -0,0 +1,113 @@
+use std::collections::VecDeque;
+use std::mem::size_of;
+use std::ops::Range;
+use crate::k8ui::ansi::AnsiStyle;
+use crate::k8ui::appstate::LogLine;
+
+/// Memory a log window keeps by default before dropping its oldest lines.
+pub const DEFAULT_MAX_BYTES: usize = 64 * 1024 * 1024;
+
+/// Lines of a log window, the oldest are dropped once they take more than `max_bytes`.
+/// Lines are addressed by their position since the buffer was cleared, so positions held
+/// by the search stay valid while the front is dropped.
+pub struct LogBuffer {
+    lines: VecDeque<LogLine>,
+    /// Position of the front line.
+    first: usize,
+    bytes: usize,
+    max_bytes: usize,
+    dropped: usize,
+}
+
+impl LogBuffer {
+    pub fn new(max_bytes: usize) -> Self {
+        Self { lines: VecDeque::new(), first: 0, bytes: 0, max_bytes, dropped: 0 }
+    }
+
+    /// Positions of the lines held, the front moves on as lines are dropped.
+    pub fn range(&self) -> Range<usize> {
+        self.first..self.first + self.lines.len()
+    }
+
+    pub fn len(&self) -> usize {
+        self.lines.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.lines.is_empty()
+    }
+
+    pub fn get(&self, position: usize) -> Option<&LogLine> {
+        self.lines.get(position.checked_sub(self.first)?)
+    }
+
+    /// Lines from `position` on, starting at the front if it was dropped already.
+    pub fn iter_from(&self, position: usize) -> impl Iterator<Item = (usize, &LogLine)> {
+        let skip = position.saturating_sub(self.first);
+        self.lines.iter().enumerate().skip(skip).map(|(i, line)| (self.first + i, line))
+    }
+
+    pub fn bytes(&self) -> usize {
+        self.bytes
+    }
+
+    /// Lines dropped for the memory cap since the buffer was cleared.
+    pub fn dropped(&self) -> usize {
+        self.dropped
+    }
+
+    pub fn set_max_bytes(&mut self, max_bytes: usize) {
+        self.max_bytes = max_bytes;
+        self.trim();
+    }
+
+    /// Appends the line, or sorts it in by time when it is older than the last one.
+    /// Returns its position, the lines after it moved one back when it was sorted in.
+    pub fn push(&mut self, line: LogLine) -> usize {
+        self.bytes += line_bytes(&line);
+        let index = match line.at {
+            Some(at) if self.lines.back().is_some_and(|last| last.at > Some(at)) => {
+                let index = self.lines.partition_point(|l| l.at <= Some(at));
+                self.lines.insert(index, line);
+                index
+            }
+            _ => {
+                self.lines.push_back(line);
+                self.lines.len() - 1
+            }
+        };
+        let position = self.first + index;
+        self.trim();
+        position
+    }
+
+    /// Takes the lines out in order, leaving the buffer cleared.
+    pub fn take(&mut self) -> VecDeque<LogLine> {
+        let lines = std::mem::take(&mut self.lines);
+        self.clear();
+        lines
+    }
+
+    pub fn clear(&mut self) {
+        self.lines.clear();
+        self.first = 0;
+        self.bytes = 0;
+        self.dropped = 0;
+    }
+
+    fn trim(&mut self) {
+        // the last line stays even when it alone is over the cap
+        while self.bytes > self.max_bytes && self.lines.len() > 1 {
+            if let Some(line) = self.lines.pop_front() {
+                self.bytes -= line_bytes(&line);
+                self.first += 1;
+                self.dropped += 1;
+            }
+        }
+    }
+}
+
+/// Roughly what a line takes on the heap, the source is shared with the other lines of the container.
+fn line_bytes(line: &LogLine) -> usize {
+    size_of::<LogLine>() + line.text.capacity() + line.styles.capacity() * size_of::<(Range<usize>, AnsiStyle)>()
+}
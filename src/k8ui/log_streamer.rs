@@ -0,0 +1,92 @@
+use std::time::Duration;
+use crossbeam::channel::Sender;
+use futures::{AsyncBufReadExt, TryStreamExt};
+use k8s_openapi::api::core::v1::Pod;
+use k8s_openapi::chrono::{DateTime, Utc};
+use kube::Api;
+use tokio::sync::oneshot;
+use tokio::task::JoinHandle;
+use crate::k8ui::appstate::{LogLine, LogRequest, LogTarget, PodKey};
+use crate::k8ui::k8api::{log_params, KubeApis};
+use crate::k8ui::log_aggregator;
+use crate::k8ui::my_thread::{send_data, ThreadMessage, UIData};
+
+/// Lines are collected for this long before they are sent, a busy pod would flood the ui otherwise.
+pub const BATCH_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Reads a pod log on the api thread runtime. A following request runs until stopped or the container stops,
+/// an aggregated one until stopped. Dropping it or the window dropping the sender of `stopped` stops it.
+pub struct LogStreamer {
+    handle: JoinHandle<()>,
+}
+
+impl LogStreamer {
+    pub fn start(apis: &KubeApis, request: LogRequest, stopped: oneshot::Receiver<()>, ui_sender: Sender<ThreadMessage>) -> Self {
+        let apis = apis.clone();
+        let handle = tokio::spawn(async move {
+            let read = async {
+                match &request.target {
+                    LogTarget::Pod(pod) => stream_lines(&apis.pods(pod.namespace.as_str()), pod, &request, &ui_sender).await,
+                    _ => log_aggregator::aggregate(&apis, &request, &ui_sender).await,
+                }
+            };
+            let result = tokio::select! {
+                result = read => result,
+                // the window doesn't wait for the request anymore
+                _ = stopped => return,
+            };
+            let error = result.err().map(|e| format!("{:#}", e));
+            send_data(&ui_sender, UIData::LogEnded(request.id, error));
+        });
+        Self { handle }
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.handle.is_finished()
+    }
+}
+
+async fn stream_lines(api_pods: &Api<Pod>, pod: &PodKey, request: &LogRequest, ui_sender: &Sender<ThreadMessage>) -> anyhow::Result<()> {
+    let mut lines = api_pods
+        .log_stream(pod.name.as_str(), &log_params(&request.query, request.follow))
+        .await?
+        .lines();
+
+    let mut batch = Vec::new();
+    let mut flush = tokio::time::interval(BATCH_INTERVAL);
+    loop {
+        tokio::select! {
+            line = lines.try_next() => match line? {
+                Some(line) => {
+                    let at = if request.query.timestamps { split_timestamp(&line).0 } else { None };
+                    batch.push(LogLine::parse(at, None, &line));
+                }
+                None => break,
+            },
+            _ = flush.tick() => if !batch.is_empty() {
+                send_data(ui_sender, UIData::Logs(request.id, std::mem::take(&mut batch)));
+            },
+        }
+    }
+    if !batch.is_empty() {
+        send_data(ui_sender, UIData::Logs(request.id, batch));
+    }
+    Ok(())
+}
+
+/// Lines read with timestamps start with an RFC 3339 time and a space.
+pub fn split_timestamp(line: &str) -> (Option<DateTime<Utc>>, &str) {
+    let Some((time, rest)) = line.split_once(' ') else {
+        return (None, line);
+    };
+    match DateTime::parse_from_rfc3339(time) {
+        Ok(at) => (Some(at.with_timezone(&Utc)), rest),
+        Err(_) => (None, line),
+    }
+}
+
+impl Drop for LogStreamer {
+    fn drop(&mut self) {
+        self.handle.abort();
+    }
+}
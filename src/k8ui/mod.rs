@@ -1,6 +1,18 @@
 pub mod k8api;
 pub mod appstate;
+pub mod ansi;
 pub mod uinormdz;
 pub mod my_thread;
 pub mod ui_component_bus;
 mod components;
+pub mod pod_watcher;
+pub mod name_match;
+pub mod selectors;
+pub mod env_resolver;
+pub mod port_forwarder;
+pub mod forward_stats;
+pub mod forward_pool;
+pub mod log_streamer;
+pub mod log_aggregator;
+pub mod log_buffer;
+pub mod structured_log;
@@ -1,15 +1,15 @@
-use std::net::SocketAddr;
+use std::collections::{BTreeMap, HashMap};
 use std::thread;
 use crossbeam::channel::{Receiver, Sender};
-use eframe::egui::TextBuffer;
-use futures::{StreamExt, TryStreamExt};
-use k8s_openapi::api::core::v1::Pod;
-use kube::{Api, Client};
-use tokio::net::TcpListener;
+use kube::Client;
+use kube::config::Kubeconfig;
 use tokio::runtime::{Runtime};
-use crate::k8ui::appstate::ShortKContainer;
-use crate::k8ui::k8api;
-use crate::k8ui::k8api::{KubeApis, refresh_apis, refresh_client, refresh_pod_list};
+use tokio::sync::oneshot;
+use crate::k8ui::appstate::{ConnectionInfo, ForwardEvent, ForwardId, ForwardInfo, ForwardRequest, ForwardTraffic, KubeconfigInfo, LogLine, LogRequest, LogRequestId, LogSource, PodKey, PodQuery, ServiceInfo, ShortKPod, WorkloadInfo};
+use crate::k8ui::k8api::{KubeApis, list_namespaces, list_services, read_kubeconfigs, refresh_apis, refresh_client, split_kubeconfig_paths};
+use crate::k8ui::log_streamer::LogStreamer;
+use crate::k8ui::pod_watcher::PodWatcher;
+use crate::k8ui::port_forwarder::PortForwarder;
 
 #[derive(Debug)]
 pub enum ThreadMessage {
@@ -19,20 +19,69 @@ pub enum ThreadMessage {
 
 #[derive(Debug)]
 pub enum UIData {
-    Pods(Vec<ShortKContainer>),
-    Logs(Vec<String>),
+    /// Full list of watched pods, replaces whatever the ui shows.
+    Pods(Vec<ShortKPod>),
+    PodUpdated(ShortKPod),
+    PodDeleted(PodKey),
+    /// Owners of the watched pods, replaces the entries with the same key.
+    Workloads(Vec<WorkloadInfo>),
+    /// Next lines for a log request, appended to what the request sent before.
+    Logs(LogRequestId, Vec<LogLine>),
+    /// Containers an aggregated request streams right now, sent whenever one attaches or detaches.
+    LogSources(LogRequestId, Vec<LogSource>),
+    /// The request read everything or its stream stopped by itself, with the reason when it failed.
+    LogEnded(LogRequestId, Option<String>),
+    Services(Vec<ServiceInfo>),
+    ForwardStarted(ForwardInfo),
+    /// Stopped on request or because the listener failed, with the reason in the latter case.
+    ForwardStopped(ForwardId, Option<String>),
+    ForwardEvent(ForwardId, ForwardEvent),
+    /// Sampled every second while a forward has traffic.
+    ForwardTraffic(ForwardId, ForwardTraffic),
+    ForwardConnection(ForwardId, ConnectionInfo),
+    /// Merged kubeconfig files, their contexts and the one the client is connected to.
+    Kubeconfig(KubeconfigInfo),
+    Namespaces(Vec<String>),
+    Error(String),
 }
 
 #[derive(Debug)]
 pub enum ApiCommand {
+    /// Kubeconfig paths separated like in `$KUBECONFIG`.
     ReloadClientWithConfig(String),
-    ReloadApisWithNameSpace(String),
-    PullPodsWithPrefix(String),
-    PullLogsForPodName(String),
-    PortForwardForPodNamePort(String, u16),
+    SwitchContext(String),
+    PullNamespaces,
+    /// `None` selects all namespaces.
+    ReloadApisWithNameSpace(Option<String>),
+    /// (Re)starts the pod watch for the current apis.
+    PullPods(PodQuery),
+    /// Lines come back as `UIData::Logs` with the request id, following requests run until the window
+    /// drops the sender of the receiver passed along.
+    PullLogsForPodName(LogRequest, oneshot::Receiver<()>),
+    /// Services of the current apis scope.
+    PullServices,
+    /// One request per port, several ports of a pod are started in one go.
+    PortForward(Vec<ForwardRequest>),
+    StopPortForward(ForwardId),
+}
+
+/// Everything the api thread keeps between commands.
+#[derive(Default)]
+struct ThreadState {
+    kubeconfig: Option<Kubeconfig>,
+    client: Option<Client>,
+    apis: Option<KubeApis>,
+    pod_watcher: Option<PodWatcher>,
+    /// Forwards keep running across namespace and context switches until stopped.
+    forwards: BTreeMap<ForwardId, PortForwarder>,
+    next_forward_id: u64,
+    /// Scope of the apis, log streams stop when it changes.
+    namespace: Option<String>,
+    log_streams: HashMap<LogRequestId, LogStreamer>,
 }
 
 pub struct ApiThread {
+    #[allow(dead_code)]
     pub thread: thread::JoinHandle<()>,
 }
 
@@ -40,30 +89,18 @@ impl ApiThread {
     pub fn new(thread_receiver: Receiver<ThreadMessage>, ui_sender: Sender<ThreadMessage>) -> Self {
         let runtime = Runtime::new().unwrap();
         let thread = thread::spawn(move || runtime.block_on(async {
-            let mut i = 0;
             println!("Thread start");
-            let mut client: Option<Client> = None;
-            let mut apis: Option<KubeApis> = None;
-
+            let mut state = ThreadState::default();
 
-            loop {
 
-                match thread_receiver.recv() {
-                    Ok(cmd) => {
-                        match cmd {
-                            ThreadMessage::Api(cmd) => {
-                                println!("recv! {:?}", cmd);
-                                api_command_matcher(cmd, &mut client, &mut apis, &ui_sender).await;
-                            }
-                            _ => println!("This shouldn't come to the thread"),
-                        }
-                    }
-                    Err(_) => {
-                        break;
+            while let Ok(cmd) = thread_receiver.recv() {
+                match cmd {
+                    ThreadMessage::Api(cmd) => {
+                        println!("recv! {:?}", cmd);
+                        api_command_matcher(cmd, &mut state, &ui_sender).await;
                     }
+                    _ => println!("This shouldn't come to the thread"),
                 }
-
-                i += 1;
             }
 
             println!("Thread exit");
@@ -73,62 +110,157 @@ impl ApiThread {
     }
 }
 
-async fn api_command_matcher(cmd: ApiCommand, client: &mut Option<Client>, apis: &mut Option<KubeApis>, ui_sender: &Sender<ThreadMessage>) {
+async fn api_command_matcher(cmd: ApiCommand, state: &mut ThreadState, ui_sender: &Sender<ThreadMessage>) {
     match cmd {
         ApiCommand::ReloadClientWithConfig(path) => {
             println!("refreshing client {}", path);
-            *client = Some(refresh_client(path.as_str()).await);
+            let (config, info) = read_kubeconfigs(&split_kubeconfig_paths(path.as_str()));
+            send_data(ui_sender, UIData::Kubeconfig(info));
+            let Some(config) = config else {
+                send_error(ui_sender, format!("No readable kubeconfig in {}", path));
+                return;
+            };
+            let current = config.current_context.clone();
+            connect(config.clone(), current, state, ui_sender).await;
+            state.kubeconfig = Some(config);
+        }
+
+        ApiCommand::SwitchContext(context) => {
+            println!("switching context {}", context);
+            match state.kubeconfig.clone() {
+                Some(config) => connect(config, Some(context), state, ui_sender).await,
+                None => send_error(ui_sender, "Kubeconfig is not loaded".to_owned()),
+            }
+        }
+
+        ApiCommand::PullNamespaces => {
+            println!("pulling namespaces");
+            match state.client.as_ref() {
+                Some(client) => pull_namespaces(client, ui_sender).await,
+                None => send_error(ui_sender, "Client is not connected".to_owned()),
+            }
         }
 
         ApiCommand::ReloadApisWithNameSpace(namespace) => {
-            println!("refreshing apis {}", namespace);
-            *apis = Some(refresh_apis(&client.as_ref().unwrap(), namespace.as_str()).await);
+            println!("refreshing apis {:?}", namespace);
+            state.pod_watcher = None;
+            if namespace != state.namespace {
+                stop_log_streams(state, "the namespace changed", ui_sender);
+                state.namespace = namespace.clone();
+            }
+            match state.client.as_ref() {
+                Some(client) => state.apis = Some(refresh_apis(client, namespace.as_deref()).await),
+                None => send_error(ui_sender, "Client is not connected".to_owned()),
+            }
+        }
+
+        ApiCommand::PullPods(query) => {
+            println!("watching pods {:?}", query);
+            // drop the previous watch first so its events don't interleave with the new one
+            state.pod_watcher = None;
+            if let Err(e) = query.validate() {
+                send_error(ui_sender, e);
+                return;
+            }
+            match state.apis.as_ref() {
+                Some(apis) => state.pod_watcher = Some(PodWatcher::start(apis.clone(), query, ui_sender.clone())),
+                None => send_error(ui_sender, "Apis are not loaded".to_owned()),
+            }
+        }
+
+        ApiCommand::PullLogsForPodName(request, stopped) => {
+            println!("pulling logs {:?}", request);
+            let Some(apis) = state.apis.as_ref() else {
+                send_data(ui_sender, UIData::LogEnded(request.id, Some("Apis are not loaded".to_owned())));
+                return;
+            };
+            state.log_streams.retain(|_, streamer| !streamer.is_finished());
+            state.log_streams.insert(request.id, LogStreamer::start(apis, request, stopped, ui_sender.clone()));
         }
 
-        ApiCommand::PullPodsWithPrefix(prefix) => {
-            println!("pulling pods {}", prefix);
-            match refresh_pod_list(prefix.as_str(), &apis.as_ref().unwrap().api_pods, &apis.as_ref().unwrap().api_cfm, &apis.as_ref().unwrap().api_secrets).await {
-                Ok(ui_pods) => ui_sender.try_send(ThreadMessage::Data(UIData::Pods(ui_pods))).unwrap(),
-                Err(e) => println!("Error generating pod ui data {}", e),
+        ApiCommand::PullServices => {
+            println!("pulling services");
+            match state.apis.as_ref() {
+                Some(apis) => match list_services(apis).await {
+                    Ok(services) => send_data(ui_sender, UIData::Services(services)),
+                    Err(e) => send_error(ui_sender, format!("Failed to list services: {:#}", e)),
+                },
+                None => send_error(ui_sender, "Apis are not loaded".to_owned()),
             }
         }
 
-        ApiCommand::PullLogsForPodName(pod_name) => {
-            println!("pulling logs {}", pod_name);
-            match k8api::logs(pod_name, &apis.as_ref().unwrap().api_pods).await {
-                Ok(lines) => ui_sender.try_send(ThreadMessage::Data(UIData::Logs(lines))).unwrap(),
-                Err(e) => println!("Error pulling logs {}", e),
+        ApiCommand::PortForward(requests) => {
+            println!("forwarding {:?}", requests);
+            let Some(apis) = state.apis.as_ref() else {
+                send_error(ui_sender, "Apis are not loaded".to_owned());
+                return;
+            };
+            state.forwards.retain(|_, forward| !forward.is_finished());
+            for request in requests {
+                state.next_forward_id += 1;
+                let id = ForwardId(state.next_forward_id);
+                let target = format!("{}:{}", request.target, request.remote_port);
+                match PortForwarder::start(id, request, apis, ui_sender.clone()).await {
+                    Ok(forward) => {
+                        send_data(ui_sender, UIData::ForwardStarted(forward.info.clone()));
+                        state.forwards.insert(id, forward);
+                    }
+                    Err(e) => send_error(ui_sender, format!("Failed to forward {}: {:#}", target, e)),
+                }
             }
         }
 
-        ApiCommand::PortForwardForPodNamePort(pod_name, port) => {
-            println!("forwarding {}:{}", pod_name, port);
-            port_forward(pod_name.as_str(), port, &apis.as_ref().unwrap().api_pods).await;//todo blocks!!!!!
+        ApiCommand::StopPortForward(id) => {
+            println!("stopping forward {:?}", id);
+            if state.forwards.remove(&id).is_some() {
+                send_data(ui_sender, UIData::ForwardStopped(id, None));
+            }
         }
     }
 }
 
-async fn port_forward(pod_name: &str, pod_port: u16, api_pods: &Api<Pod>) {
-    let addr = SocketAddr::from(([127, 0, 0, 1], pod_port + 1));
-    let g: &'static str = pod_name.to_string().clone().leak();
-    let bind = TcpListener::bind(addr).await.unwrap();
-    let server = tokio_stream::wrappers::TcpListenerStream::new(bind)
-        .take_until(tokio::signal::ctrl_c())
-        .try_for_each(|client_conn| async {
-            if let Ok(peer_addr) = client_conn.peer_addr() {
-                println!("new conn {}", peer_addr);
-            }
-            let api_clone = api_pods.clone();
-            tokio::spawn(async move {
-                if let Err(e) = k8api::forward_connection(api_clone, g, pod_port, client_conn).await {
-                    println!("failed to forward connection {}", error = e.as_ref() as &dyn std::error::Error);
-                }
-            });
-            // keep the server running
-            Ok(())
-        });
+/// Replaces the client with one for `context`. Apis built on the old client are dropped,
+/// the ui is expected to reload them for the new namespace.
+async fn connect(config: Kubeconfig, context: Option<String>, state: &mut ThreadState, ui_sender: &Sender<ThreadMessage>) {
+    state.pod_watcher = None;
+    state.apis = None;
+    stop_log_streams(state, "the context changed", ui_sender);
+    match refresh_client(config, context.clone()).await {
+        Ok(new_client) => {
+            pull_namespaces(&new_client, ui_sender).await;
+            state.client = Some(new_client);
+        }
+        Err(e) => {
+            state.client = None;
+            send_error(ui_sender, format!("Failed to connect to {}: {:#}", context.unwrap_or_default(), e));
+        }
+    }
+}
 
-    if let Err(e) = server.await {
-        println!("server error {}", error = &e as &dyn std::error::Error);
+/// Streams read through apis that are replaced, their windows are told why they ended.
+fn stop_log_streams(state: &mut ThreadState, reason: &str, ui_sender: &Sender<ThreadMessage>) {
+    for (id, streamer) in state.log_streams.drain() {
+        // a finished one told its window already
+        if !streamer.is_finished() {
+            send_data(ui_sender, UIData::LogEnded(id, Some(reason.to_owned())));
+        }
     }
-}
\ No newline at end of file
+}
+
+async fn pull_namespaces(client: &Client, ui_sender: &Sender<ThreadMessage>) {
+    match list_namespaces(client).await {
+        Ok(namespaces) => send_data(ui_sender, UIData::Namespaces(namespaces)),
+        Err(e) => send_error(ui_sender, format!("Failed to list namespaces: {:#}", e)),
+    }
+}
+
+pub fn send_data(ui_sender: &Sender<ThreadMessage>, data: UIData) {
+    if let Err(e) = ui_sender.try_send(ThreadMessage::Data(data)) {
+        println!("Error sending to ui {}", e);
+    }
+}
+
+fn send_error(ui_sender: &Sender<ThreadMessage>, error: String) {
+    println!("{}", error);
+    send_data(ui_sender, UIData::Error(error));
+}
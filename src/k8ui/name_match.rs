@@ -0,0 +1,240 @@
+use std::fmt;
+use std::ops::Range;
+use regex::Regex;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub enum NameMatchMode {
+    #[default]
+    Prefix,
+    Substring,
+    Glob,
+    Regex,
+    Fuzzy,
+}
+
+impl NameMatchMode {
+    pub const ALL: [NameMatchMode; 5] = [NameMatchMode::Prefix, NameMatchMode::Substring, NameMatchMode::Glob, NameMatchMode::Regex, NameMatchMode::Fuzzy];
+}
+
+impl fmt::Display for NameMatchMode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            NameMatchMode::Prefix => "Prefix",
+            NameMatchMode::Substring => "Substring",
+            NameMatchMode::Glob => "Glob",
+            NameMatchMode::Regex => "Regex",
+            NameMatchMode::Fuzzy => "Fuzzy",
+        };
+        f.write_str(name)
+    }
+}
+
+/// Where a name matched, byte ranges are used to highlight the column heading.
+/// Higher scores rank first, only fuzzy matching produces different scores.
+#[derive(Debug, Clone, Default)]
+pub struct NameMatch {
+    pub score: i64,
+    pub ranges: Vec<Range<usize>>,
+}
+
+#[derive(Debug, Clone, Default)]
+pub enum NameMatcher {
+    #[default]
+    Any,
+    Prefix(String),
+    /// Lowercased, substring matching ignores case.
+    Substring(String),
+    /// Globs are translated to an anchored regex.
+    Pattern(Regex),
+    Fuzzy(String),
+}
+
+impl NameMatcher {
+    pub fn new(mode: NameMatchMode, pattern: &str) -> Result<Self, String> {
+        if pattern.is_empty() {
+            return Ok(NameMatcher::Any);
+        }
+        let matcher = match mode {
+            NameMatchMode::Prefix => NameMatcher::Prefix(pattern.to_owned()),
+            NameMatchMode::Substring => NameMatcher::Substring(pattern.to_ascii_lowercase()),
+            NameMatchMode::Glob => NameMatcher::Pattern(Regex::new(&glob_to_regex(pattern))
+                .map_err(|e| format!("invalid glob \"{}\": {}", pattern, e))?),
+            NameMatchMode::Regex => NameMatcher::Pattern(Regex::new(pattern)
+                .map_err(|e| format!("invalid regex: {}", e))?),
+            NameMatchMode::Fuzzy => NameMatcher::Fuzzy(pattern.to_ascii_lowercase()),
+        };
+        Ok(matcher)
+    }
+
+    pub fn find(&self, name: &str) -> Option<NameMatch> {
+        match self {
+            NameMatcher::Any => Some(NameMatch::default()),
+            NameMatcher::Prefix(prefix) => {
+                let matched: Range<usize> = 0..prefix.len();
+                name.starts_with(prefix.as_str()).then(|| NameMatch { score: 0, ranges: vec![matched] })
+            }
+            NameMatcher::Substring(needle) => {
+                let haystack = name.to_ascii_lowercase();
+                let ranges: Vec<Range<usize>> = haystack.match_indices(needle.as_str())
+                    .map(|(start, m)| start..start + m.len())
+                    .collect();
+                (!ranges.is_empty()).then_some(NameMatch { score: 0, ranges })
+            }
+            NameMatcher::Pattern(regex) => {
+                let ranges: Vec<Range<usize>> = regex.find_iter(name)
+                    .map(|m| m.range())
+                    .filter(|r| !r.is_empty())
+                    .collect();
+                regex.is_match(name).then_some(NameMatch { score: 0, ranges })
+            }
+            NameMatcher::Fuzzy(pattern) => fuzzy_match(pattern, name),
+        }
+    }
+
+    pub fn matches(&self, name: &str) -> bool {
+        self.find(name).is_some()
+    }
+}
+
+/// `*` is any run of characters, `?` a single one and `[...]` a character class.
+fn glob_to_regex(glob: &str) -> String {
+    let mut regex = String::from("^");
+    let mut in_class = false;
+    for c in glob.chars() {
+        match c {
+            '[' if !in_class => {
+                in_class = true;
+                regex.push('[');
+            }
+            ']' if in_class => {
+                in_class = false;
+                regex.push(']');
+            }
+            '!' if in_class && regex.ends_with('[') => regex.push('^'),
+            _ if in_class => regex.push(c),
+            '*' => regex.push_str(".*"),
+            '?' => regex.push('.'),
+            _ => regex.push_str(&regex::escape(&c.to_string())),
+        }
+    }
+    regex.push('$');
+    regex
+}
+
+/// Subsequence match in the spirit of fzf: every pattern character has to appear in order.
+/// Consecutive characters and characters starting a name segment (after `-`, `.` or `_`) score higher,
+/// gaps cost a little so tighter matches rank first.
+fn fuzzy_match(pattern: &str, name: &str) -> Option<NameMatch> {
+    let lower = name.to_ascii_lowercase();
+    let bytes = lower.as_bytes();
+    let mut pattern_chars = pattern.bytes().peekable();
+    let mut score = 0i64;
+    let mut ranges: Vec<Range<usize>> = Vec::new();
+    let mut last_match: Option<usize> = None;
+
+    for (i, b) in bytes.iter().enumerate() {
+        let Some(&wanted) = pattern_chars.peek() else {
+            break;
+        };
+        if *b != wanted {
+            continue;
+        }
+        pattern_chars.next();
+
+        score += 16;
+        if i == 0 || matches!(bytes[i - 1], b'-' | b'.' | b'_') {
+            score += 8;
+        }
+        match last_match {
+            Some(last) if last + 1 == i => {
+                score += 12;
+                ranges.last_mut().unwrap().end = i + 1;
+            }
+            Some(last) => {
+                score -= (i - last - 1) as i64;
+                ranges.push(i..i + 1);
+            }
+            None => {
+                score -= i as i64;
+                ranges.push(i..i + 1);
+            }
+        }
+        last_match = Some(i);
+    }
+
+    if pattern_chars.peek().is_some() {
+        return None;
+    }
+    Some(NameMatch { score, ranges })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn glob(pattern: &str) -> NameMatcher {
+        NameMatcher::new(NameMatchMode::Glob, pattern).unwrap()
+    }
+
+    #[test]
+    fn glob_translation() {
+        assert_eq!(glob_to_regex("web-*"), "^web\\-.*$");
+        assert_eq!(glob_to_regex("db-?"), "^db\\-.$");
+        assert_eq!(glob_to_regex("a.b"), "^a\\.b$");
+        assert_eq!(glob_to_regex("pod-[0-9]"), "^pod\\-[0-9]$");
+        assert_eq!(glob_to_regex("pod-[!0-9]"), "^pod\\-[^0-9]$");
+        // only first in a class it negates
+        assert_eq!(glob_to_regex("[a!]"), "^[a!]$");
+        assert_eq!(glob_to_regex("x!"), "^x!$");
+    }
+
+    #[test]
+    fn glob_matches_whole_names() {
+        let matcher = glob("web-*-[0-9]");
+        assert!(matcher.matches("web-abc-1"));
+        assert!(!matcher.matches("web-abc-x"));
+        assert!(!matcher.matches("my-web-abc-1"));
+        assert!(glob("web-?").matches("web-a"));
+        assert!(!glob("web-?").matches("web-ab"));
+        assert!(glob("a.b").matches("a.b"));
+        assert!(!glob("a.b").matches("axb"));
+    }
+
+    #[test]
+    fn glob_negated_class() {
+        let matcher = glob("pod-[!0-9]*");
+        assert!(matcher.matches("pod-abc"));
+        assert!(!matcher.matches("pod-1bc"));
+    }
+
+    #[test]
+    fn glob_with_unclosed_class_is_an_error() {
+        assert!(NameMatcher::new(NameMatchMode::Glob, "pod-[0-9").is_err());
+    }
+
+    #[test]
+    fn glob_ranges_cover_the_name() {
+        assert_eq!(glob("web-*").find("web-1").unwrap().ranges, vec![0..5]);
+        assert!(glob("*").find("").unwrap().ranges.is_empty());
+    }
+
+    #[test]
+    fn other_modes() {
+        assert!(NameMatcher::new(NameMatchMode::Prefix, "web").unwrap().matches("web-1"));
+        assert!(!NameMatcher::new(NameMatchMode::Prefix, "Web").unwrap().matches("web-1"));
+        let substring = NameMatcher::new(NameMatchMode::Substring, "DB").unwrap();
+        assert_eq!(substring.find("db-shop-db").unwrap().ranges, vec![0..2, 8..10]);
+        assert!(NameMatcher::new(NameMatchMode::Regex, "(").is_err());
+        assert!(NameMatcher::new(NameMatchMode::Regex, "").unwrap().matches("anything"));
+    }
+
+    #[test]
+    fn fuzzy_prefers_tight_matches() {
+        let matcher = NameMatcher::new(NameMatchMode::Fuzzy, "web").unwrap();
+        let tight = matcher.find("web-1").unwrap();
+        let loose = matcher.find("w-e-b").unwrap();
+        assert!(tight.score > loose.score);
+        assert_eq!(tight.ranges, vec![0..3]);
+        assert!(matcher.find("bew").is_none());
+    }
+}
@@ -0,0 +1,152 @@
+use std::collections::{BTreeSet, HashSet};
+use std::time::Duration;
+use crossbeam::channel::Sender;
+use futures::{StreamExt, TryStreamExt};
+use k8s_openapi::api::core::v1::Pod;
+use kube::runtime::{reflector, watcher, WatchStreamExt};
+use tokio::task::JoinHandle;
+use tokio::time::MissedTickBehavior;
+use crate::k8ui::appstate::{PodKey, PodQuery, WorkloadKey};
+use crate::k8ui::env_resolver::{EnvCache, SOURCES_TTL};
+use crate::k8ui::k8api::{KubeApis, OwnerCache, pod_to_short, resolve_owner, workload_info};
+use crate::k8ui::name_match::NameMatcher;
+use crate::k8ui::my_thread::{send_data, ThreadMessage, UIData};
+
+/// Workloads of changed pods are pulled at most this often, a rollout changes many pods at once.
+const WORKLOAD_REFRESH: Duration = Duration::from_secs(1);
+
+/// Reflector backed pod watch running on the api thread runtime.
+/// Streams pod changes to the ui until dropped.
+pub struct PodWatcher {
+    handle: JoinHandle<()>,
+}
+
+impl PodWatcher {
+    pub fn start(apis: KubeApis, query: PodQuery, ui_sender: Sender<ThreadMessage>) -> Self {
+        let (reader, writer) = reflector::store::<Pod>();
+
+        let matcher = query.name_matcher().unwrap_or_default();
+
+        let handle = tokio::spawn(async move {
+            let mut owners = OwnerCache::new();
+            let mut env_cache = EnvCache::default();
+            // replica counts move together with the pods, owners of changed pods are pulled on the next tick
+            let mut stale_workloads = BTreeSet::new();
+            let mut refresh = tokio::time::interval(WORKLOAD_REFRESH);
+            refresh.set_missed_tick_behavior(MissedTickBehavior::Delay);
+            // pods without events resolve their environment again once its sources are stale
+            let mut env_refresh = tokio::time::interval_at(tokio::time::Instant::now() + SOURCES_TTL, SOURCES_TTL);
+            env_refresh.set_missed_tick_behavior(MissedTickBehavior::Delay);
+            let mut events = watcher(apis.api_pods.clone(), watcher_config(&query))
+                .default_backoff()
+                .reflect(writer)
+                .boxed();
+
+            loop {
+                let event = tokio::select! {
+                    event = events.try_next() => event,
+                    _ = refresh.tick(), if !stale_workloads.is_empty() => {
+                        send_workloads(stale_workloads.iter(), &apis, &ui_sender).await;
+                        stale_workloads.clear();
+                        continue;
+                    }
+                    _ = env_refresh.tick() => {
+                        let state = reader.state();
+                        let stale: Vec<_> = state.iter()
+                            .filter(|p| matches(p, &matcher) && env_cache.is_stale(p.metadata.uid.as_deref().unwrap_or_default()))
+                            .collect();
+                        for pod in stale {
+                            send_pod(pod, &apis, &mut owners, &mut env_cache, &ui_sender).await;
+                        }
+                        continue;
+                    }
+                };
+                match event {
+                    Ok(Some(event)) => match event {
+                        watcher::Event::Apply(pod) => {
+                            if matches(&pod, &matcher) {
+                                let owner = send_pod(&pod, &apis, &mut owners, &mut env_cache, &ui_sender).await;
+                                stale_workloads.extend(owner);
+                            }
+                        }
+                        watcher::Event::Delete(pod) => {
+                            env_cache.remove(pod.metadata.uid.as_deref().unwrap_or_default());
+                            stale_workloads.extend(resolve_owner(&pod, &apis, &mut owners).await);
+                            let key = PodKey::new(pod.metadata.namespace.unwrap_or_default(), pod.metadata.name.unwrap_or_default());
+                            send_data(&ui_sender, UIData::PodDeleted(key));
+                        }
+                        // the store is swapped in one go on InitDone, send the full list from it
+                        watcher::Event::Init | watcher::Event::InitApply(_) => {}
+                        watcher::Event::InitDone => {
+                            let mut pods = Vec::new();
+                            let mut workloads = BTreeSet::new();
+                            let state = reader.state();
+                            env_cache.retain(&state.iter().filter_map(|p| p.metadata.uid.as_deref()).collect::<HashSet<_>>());
+                            for pod in state.iter().filter(|p| matches(p, &matcher)) {
+                                let owner = resolve_owner(pod, &apis, &mut owners).await;
+                                workloads.extend(owner.clone());
+                                match pod_to_short(pod, owner, &apis, &mut env_cache).await {
+                                    Ok(short) => pods.push(short),
+                                    Err(e) => println!("Error generating pod ui data {:#}", e),
+                                }
+                            }
+                            pods.sort_by_key(|p| p.key());
+                            send_workloads(workloads.iter(), &apis, &ui_sender).await;
+                            stale_workloads.clear();
+                            send_data(&ui_sender, UIData::Pods(pods));
+                        }
+                    },
+                    Ok(None) => break,
+                    Err(e) => send_data(&ui_sender, UIData::Error(format!("Pod watch failed: {}", e))),
+                }
+            }
+            println!("pod watch finished");
+        });
+
+        Self { handle }
+    }
+}
+
+impl Drop for PodWatcher {
+    fn drop(&mut self) {
+        self.handle.abort();
+    }
+}
+
+pub fn watcher_config(query: &PodQuery) -> watcher::Config {
+    let mut config = watcher::Config::default();
+    if !query.label_selector.trim().is_empty() {
+        config = config.labels(query.label_selector.trim());
+    }
+    if !query.field_selector.trim().is_empty() {
+        config = config.fields(query.field_selector.trim());
+    }
+    config
+}
+
+fn matches(pod: &Pod, matcher: &NameMatcher) -> bool {
+    matcher.matches(pod.metadata.name.as_deref().unwrap_or_default())
+}
+
+/// Returns the owner of the pod, its counts are pulled later.
+async fn send_pod(pod: &Pod, apis: &KubeApis, owners: &mut OwnerCache, env_cache: &mut EnvCache, ui_sender: &Sender<ThreadMessage>) -> Option<WorkloadKey> {
+    let owner = resolve_owner(pod, apis, owners).await;
+    match pod_to_short(pod, owner.clone(), apis, env_cache).await {
+        Ok(short) => send_data(ui_sender, UIData::PodUpdated(short)),
+        Err(e) => println!("Error generating pod ui data {:#}", e),
+    }
+    owner
+}
+
+async fn send_workloads<'a>(keys: impl Iterator<Item = &'a WorkloadKey>, apis: &KubeApis, ui_sender: &Sender<ThreadMessage>) {
+    let mut workloads = Vec::new();
+    for key in keys {
+        match workload_info(key, apis).await {
+            Ok(info) => workloads.push(info),
+            Err(e) => println!("Error pulling workload {} {:#}", key, e),
+        }
+    }
+    if !workloads.is_empty() {
+        send_data(ui_sender, UIData::Workloads(workloads));
+    }
+}
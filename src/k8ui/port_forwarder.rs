@@ -0,0 +1,398 @@
+use std::fmt;
+use std::io::ErrorKind;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::Ordering;
+use std::time::{Duration, Instant};
+use anyhow::Context;
+use crossbeam::channel::Sender;
+use k8s_openapi::api::core::v1::{Pod, Service};
+use k8s_openapi::api::discovery::v1::EndpointSlice;
+use k8s_openapi::apimachinery::pkg::util::intstr::IntOrString;
+use k8s_openapi::chrono::{DateTime, Utc};
+use kube::Api;
+use kube::api::{ListParams, Portforwarder};
+use tokio::io::{AsyncRead, AsyncWrite, DuplexStream};
+use tokio::net::TcpListener;
+use tokio::task::{JoinHandle, JoinSet};
+use tokio::time::MissedTickBehavior;
+use crate::k8ui::appstate::{ConnectionInfo, ForwardEvent, ForwardId, ForwardInfo, ForwardRequest, ForwardStats, ForwardTarget, PodKey, ServiceKey};
+use crate::k8ui::forward_pool::{pipe_stream, SessionPools};
+use crate::k8ui::forward_stats::{CountingStream, TrafficCounters};
+use crate::k8ui::k8api;
+use crate::k8ui::k8api::{is_pod_ready, workload_selector, KubeApis};
+use crate::k8ui::selectors::match_labels_string;
+use crate::k8ui::my_thread::{send_data, ThreadMessage, UIData};
+
+/// Local listener forwarding every accepted connection to a pod port.
+/// Runs on the api thread runtime until dropped, dropping it also closes open connections.
+pub struct PortForwarder {
+    pub info: ForwardInfo,
+    handle: JoinHandle<()>,
+}
+
+impl PortForwarder {
+    /// Binds right away so bind errors are reported to the caller instead of from the task.
+    pub async fn start(id: ForwardId, request: ForwardRequest, apis: &KubeApis, ui_sender: Sender<ThreadMessage>) -> anyhow::Result<Self> {
+        let route = match &request.target {
+            ForwardTarget::Pod(pod) => Route::Pod(pod.name.clone()),
+            ForwardTarget::Workload(workload) => Route::dynamic(Backends::Selector(workload_selector(workload, apis).await?)),
+            ForwardTarget::Service(service) => Route::dynamic(service_backends(service, request.remote_port, apis).await?),
+        };
+        let route = Arc::new(route);
+        let namespace = request.target.namespace();
+        let (api_pods, api_slices) = (apis.pods(namespace), apis.namespaced::<EndpointSlice>(namespace));
+
+        let pools = request.pooled.then(|| Arc::new(SessionPools::new(apis.client.clone(), namespace.to_owned())));
+
+        let (listener, requested_port) = bind(&request).await?;
+        let ForwardRequest { target, remote_port, pooled, .. } = request;
+        let info = ForwardInfo {
+            id,
+            target,
+            remote_port,
+            local_addr: listener.local_addr()?,
+            requested_port,
+            pooled,
+            events: vec![],
+            stats: ForwardStats::default(),
+        };
+
+        let namespace = info.target.namespace().to_owned();
+        let handle = tokio::spawn(async move {
+            // owned by the task, aborting the task drops and aborts the connections
+            let mut connections = JoinSet::new();
+            let counters = Arc::new(TrafficCounters::default());
+            let mut sampling = tokio::time::interval(TRAFFIC_SAMPLE_INTERVAL);
+            sampling.set_missed_tick_behavior(MissedTickBehavior::Skip);
+            let mut last_sample = counters.snapshot();
+            let mut was_changed = false;
+
+            let error = loop {
+                tokio::select! {
+                    accepted = listener.accept() => match accepted {
+                        Ok((client_conn, peer_addr)) => {
+                            println!("new conn {}", peer_addr);
+                            let index = counters.accepted.fetch_add(1, Ordering::Relaxed) + 1;
+                            counters.open.fetch_add(1, Ordering::Relaxed);
+                            let mut log = ConnectionInfo {
+                                index,
+                                peer: peer_addr,
+                                opened: Utc::now(),
+                                closed: None,
+                                bytes_up: 0,
+                                bytes_down: 0,
+                                error: None,
+                            };
+                            send_data(&ui_sender, UIData::ForwardConnection(id, log.clone()));
+
+                            let connection_counters = Arc::new(TrafficCounters::default());
+                            let client_conn = CountingStream::new(client_conn, connection_counters.clone(), counters.clone());
+                            let forward_counters = counters.clone();
+                            let connection = Connection {
+                                id,
+                                namespace: namespace.clone(),
+                                port: remote_port,
+                                api_pods: api_pods.clone(),
+                                api_slices: api_slices.clone(),
+                                pools: pools.clone(),
+                                ui_sender: ui_sender.clone(),
+                            };
+                            let route = route.clone();
+                            connections.spawn(async move {
+                                if let Err(e) = connection.forward(&route, client_conn).await {
+                                    println!("failed to forward connection {:#}", e);
+                                    log.error = Some(format!("{:#}", e));
+                                }
+                                forward_counters.open.fetch_sub(1, Ordering::Relaxed);
+                                let traffic = connection_counters.snapshot();
+                                log.closed = Some(traffic.at);
+                                log.bytes_up = traffic.bytes_up;
+                                log.bytes_down = traffic.bytes_down;
+                                send_data(&connection.ui_sender, UIData::ForwardConnection(id, log));
+                            });
+                        }
+                        Err(e) => break e,
+                    },
+                    // reap finished connections so the set doesn't grow
+                    Some(_) = connections.join_next(), if !connections.is_empty() => {}
+                    _ = sampling.tick() => {
+                        let sample = counters.snapshot();
+                        let changed = sample.differs(&last_sample);
+                        // one more sample after traffic stops so the ui sees the rate drop to 0
+                        if changed || was_changed {
+                            send_data(&ui_sender, UIData::ForwardTraffic(id, sample.clone()));
+                        }
+                        was_changed = changed;
+                        last_sample = sample;
+                    }
+                }
+            };
+            send_data(&ui_sender, UIData::ForwardStopped(id, Some(format!("Forward listener failed: {}", error))));
+        });
+
+        Ok(Self { info, handle })
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.handle.is_finished()
+    }
+}
+
+/// Where connections of one forward go.
+enum Route {
+    Pod(String),
+    /// Sticks to the pod it picked as long as that one stays Ready.
+    Dynamic { backends: Backends, current: Mutex<Option<String>>, ready: tokio::sync::Mutex<Option<ReadyBackends>> },
+}
+
+impl Route {
+    fn dynamic(backends: Backends) -> Self {
+        Route::Dynamic { backends, current: Mutex::new(None), ready: tokio::sync::Mutex::new(None) }
+    }
+}
+
+/// The last listing of the Ready backends, shared by the connections accepted shortly after it.
+struct ReadyBackends {
+    listed: Instant,
+    backends: Vec<Backend>,
+}
+
+/// How the Ready pods of a workload or service are found.
+enum Backends {
+    /// Pods of a workload, the forwarded port is the container port.
+    Selector(String),
+    Service {
+        name: String,
+        /// Matches the port names in the EndpointSlices, empty for unnamed single port services.
+        port_name: String,
+        /// Used when the cluster doesn't report EndpointSlices for the service.
+        selector: Option<String>,
+        target_port: IntOrString,
+    },
+}
+
+impl fmt::Display for Backends {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Backends::Selector(selector) => f.write_str(selector),
+            Backends::Service { name, .. } => write!(f, "service {}", name),
+        }
+    }
+}
+
+/// A Ready pod and the port to forward to on it.
+#[derive(Clone)]
+struct Backend {
+    pod_name: String,
+    port: u16,
+    created: Option<DateTime<Utc>>,
+}
+
+async fn service_backends(service: &ServiceKey, service_port: u16, apis: &KubeApis) -> anyhow::Result<Backends> {
+    let spec = apis.namespaced::<Service>(&service.namespace).get(&service.name).await?
+        .spec.unwrap_or_default();
+    let port = spec.ports.iter().flatten()
+        .find(|p| p.port == service_port as i32)
+        .with_context(|| format!("service {} has no port {}", service, service_port))?;
+    Ok(Backends::Service {
+        name: service.name.clone(),
+        port_name: port.name.clone().unwrap_or_default(),
+        selector: spec.selector.as_ref().map(match_labels_string).filter(|s| !s.is_empty()),
+        target_port: port.target_port.clone().unwrap_or(IntOrString::Int(port.port)),
+    })
+}
+
+const TRAFFIC_SAMPLE_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Ready backends are listed at most this often, a burst of connections is routed by one listing.
+const READY_BACKENDS_TTL: Duration = Duration::from_secs(2);
+
+/// Pods tried for a connection before giving up, each one that refuses the portforward is skipped.
+const MAX_ATTEMPTS: usize = 3;
+
+struct Connection {
+    id: ForwardId,
+    namespace: String,
+    port: u16,
+    api_pods: Api<Pod>,
+    api_slices: Api<EndpointSlice>,
+    pools: Option<Arc<SessionPools>>,
+    ui_sender: Sender<ThreadMessage>,
+}
+
+/// The pod end of one forwarded connection.
+enum Upstream {
+    Forwarder(Portforwarder),
+    /// A slot of a websocket shared with other connections.
+    Pooled(DuplexStream),
+}
+
+impl Upstream {
+    async fn pipe(self, port: u16, client_conn: impl AsyncRead + AsyncWrite + Unpin) -> anyhow::Result<()> {
+        match self {
+            Upstream::Forwarder(forwarder) => k8api::pipe_forwarded(forwarder, port, client_conn).await,
+            Upstream::Pooled(stream) => pipe_stream(stream, client_conn).await,
+        }
+    }
+}
+
+impl Connection {
+    async fn forward(&self, route: &Route, client_conn: impl AsyncRead + AsyncWrite + Unpin) -> anyhow::Result<()> {
+        let (backends, current, ready) = match route {
+            Route::Pod(pod_name) => return self.open(pod_name, self.port).await?.pipe(self.port, client_conn).await,
+            Route::Dynamic { backends, current, ready } => (backends, current, ready),
+        };
+
+        let mut refused = Vec::new();
+        for _ in 0..MAX_ATTEMPTS {
+            let backend = self.pick(backends, current, ready, &refused).await?;
+            match self.open(&backend.pod_name, backend.port).await {
+                Ok(upstream) => return upstream.pipe(backend.port, client_conn).await,
+                Err(e) => {
+                    self.event(None, format!("{} refused the forward: {}", backend.pod_name, e));
+                    current.lock().unwrap().take_if(|c| *c == backend.pod_name);
+                    refused.push(backend.pod_name);
+                }
+            }
+        }
+        anyhow::bail!("no pod of {} accepted the connection", backends)
+    }
+
+    async fn open(&self, pod_name: &str, port: u16) -> anyhow::Result<Upstream> {
+        match &self.pools {
+            Some(pools) => Ok(Upstream::Pooled(pools.get(pod_name, port).take().await?)),
+            None => Ok(Upstream::Forwarder(self.api_pods.portforward(pod_name, &[port]).await?)),
+        }
+    }
+
+    /// Keeps the current pod while it is Ready, otherwise moves to the oldest Ready one.
+    async fn pick(&self, backends: &Backends, current: &Mutex<Option<String>>, ready: &tokio::sync::Mutex<Option<ReadyBackends>>,
+                  refused: &[String]) -> anyhow::Result<Backend> {
+        let mut ready = self.cached_ready_backends(backends, ready).await?;
+        ready.retain(|b| !refused.contains(&b.pod_name));
+
+        let mut current = current.lock().unwrap();
+        if let Some(position) = ready.iter().position(|b| Some(&b.pod_name) == current.as_ref()) {
+            return Ok(ready.swap_remove(position));
+        }
+
+        let picked = ready.into_iter()
+            .min_by(|a, b| a.created.cmp(&b.created).then_with(|| a.pod_name.cmp(&b.pod_name)))
+            .with_context(|| format!("no Ready pod in {}", backends))?;
+        let message = match current.as_ref() {
+            Some(previous) => format!("{} is not Ready anymore, routing to {}:{}", previous, picked.pod_name, picked.port),
+            None => format!("Routing to {}:{}", picked.pod_name, picked.port),
+        };
+        *current = Some(picked.pod_name.clone());
+        drop(current);
+
+        self.event(Some(PodKey::new(self.namespace.clone(), picked.pod_name.clone())), message);
+        Ok(picked)
+    }
+
+    /// Lists the Ready backends unless a listing is recent enough, connections arriving meanwhile wait for it.
+    async fn cached_ready_backends(&self, backends: &Backends, ready: &tokio::sync::Mutex<Option<ReadyBackends>>) -> anyhow::Result<Vec<Backend>> {
+        let mut ready = ready.lock().await;
+        if let Some(listing) = ready.as_ref().filter(|r| r.listed.elapsed() < READY_BACKENDS_TTL) {
+            return Ok(listing.backends.clone());
+        }
+        let backends = self.ready_backends(backends).await?;
+        *ready = Some(ReadyBackends { listed: Instant::now(), backends: backends.clone() });
+        Ok(backends)
+    }
+
+    async fn ready_backends(&self, backends: &Backends) -> anyhow::Result<Vec<Backend>> {
+        match backends {
+            Backends::Selector(selector) => Ok(self.ready_pods(selector).await?.into_iter()
+                .map(|pod| Backend {
+                    pod_name: pod.metadata.name.clone().unwrap_or_default(),
+                    port: self.port,
+                    created: pod.metadata.creation_timestamp.as_ref().map(|t| t.0),
+                })
+                .collect()),
+            Backends::Service { name, port_name, selector, target_port } => {
+                let slices = self.api_slices.list(&ListParams::default().labels(&format!("kubernetes.io/service-name={}", name))).await?;
+                if !slices.items.is_empty() {
+                    return Ok(slice_backends(&slices.items, port_name));
+                }
+                // no EndpointSlices, resolve the target port on the selected pods ourselves
+                let selector = selector.as_deref().with_context(|| format!("service {} has no selector and no endpoints", name))?;
+                Ok(self.ready_pods(selector).await?.into_iter()
+                    .filter_map(|pod| Some(Backend {
+                        port: resolve_target_port(&pod, target_port)?,
+                        pod_name: pod.metadata.name.clone().unwrap_or_default(),
+                        created: pod.metadata.creation_timestamp.as_ref().map(|t| t.0),
+                    }))
+                    .collect())
+            }
+        }
+    }
+
+    async fn ready_pods(&self, selector: &str) -> anyhow::Result<Vec<Pod>> {
+        let pods = self.api_pods.list(&ListParams::default().labels(selector)).await?;
+        Ok(pods.items.into_iter().filter(is_pod_ready).collect())
+    }
+
+    fn event(&self, routed_to: Option<PodKey>, message: String) {
+        let event = ForwardEvent { at: Utc::now(), routed_to, message };
+        send_data(&self.ui_sender, UIData::ForwardEvent(self.id, event));
+    }
+}
+
+/// Ready pod endpoints, the slice port with the service port's name already carries the resolved target port.
+fn slice_backends(slices: &[EndpointSlice], port_name: &str) -> Vec<Backend> {
+    let mut backends = Vec::new();
+    for slice in slices {
+        let port = slice.ports.iter().flatten()
+            .find(|p| p.name.as_deref().unwrap_or_default() == port_name)
+            .and_then(|p| p.port);
+        let Some(port) = port else {
+            continue;
+        };
+        for endpoint in slice.endpoints.iter() {
+            // unknown readiness counts as ready
+            let ready = endpoint.conditions.as_ref().and_then(|c| c.ready).unwrap_or(true);
+            let pod = endpoint.target_ref.as_ref()
+                .filter(|r| r.kind.as_deref() == Some("Pod"))
+                .and_then(|r| r.name.clone());
+            if let (true, Some(pod_name)) = (ready, pod) {
+                backends.push(Backend { pod_name, port: port as u16, created: None });
+            }
+        }
+    }
+    backends
+}
+
+/// A numeric target port is used as is, a named one is looked up in the container ports.
+fn resolve_target_port(pod: &Pod, target_port: &IntOrString) -> Option<u16> {
+    match target_port {
+        IntOrString::Int(port) => Some(*port as u16),
+        IntOrString::String(name) => pod.spec.as_ref()?.containers.iter()
+            .flat_map(|c| c.ports.iter().flatten())
+            .find(|p| p.name.as_deref() == Some(name.as_str()))
+            .map(|p| p.container_port as u16),
+    }
+}
+
+/// Tries the requested port first (the remote one by default) and lets the os pick one when it is taken
+/// or needs privileges, the requested port is returned along in that case.
+async fn bind(request: &ForwardRequest) -> anyhow::Result<(TcpListener, Option<u16>)> {
+    let port = request.local_port.unwrap_or(request.remote_port);
+    let ip = request.bind.ip();
+    match TcpListener::bind(SocketAddr::new(ip, port)).await {
+        Ok(listener) => Ok((listener, None)),
+        Err(e) if port != 0 && matches!(e.kind(), ErrorKind::AddrInUse | ErrorKind::PermissionDenied) => {
+            println!("{}:{} not available ({}), picking a free port", ip, port, e);
+            let listener = TcpListener::bind(SocketAddr::new(ip, 0)).await?;
+            Ok((listener, Some(port)))
+        }
+        Err(e) => Err(e.into()),
+    }
+}
+
+impl Drop for PortForwarder {
+    fn drop(&mut self) {
+        self.handle.abort();
+    }
+}
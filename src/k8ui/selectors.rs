@@ -0,0 +1,255 @@
+//! Client side validation of label and field selectors so typos are reported
+//! before the watch is started instead of as an endless stream of api errors.
+//!
+//! https://kubernetes.io/docs/concepts/overview/working-with-objects/labels/#label-selectors
+
+use std::collections::BTreeMap;
+use k8s_openapi::apimachinery::pkg::apis::meta::v1::LabelSelector;
+
+/// Pod fields the api server accepts in field selectors.
+pub const POD_SELECTABLE_FIELDS: [&str; 11] = [
+    "metadata.name",
+    "metadata.namespace",
+    "spec.nodeName",
+    "spec.restartPolicy",
+    "spec.schedulerName",
+    "spec.serviceAccountName",
+    "spec.hostNetwork",
+    "status.phase",
+    "status.podIP",
+    "status.podIPs",
+    "status.nominatedNodeName",
+];
+
+pub fn validate_label_selector(selector: &str) -> Result<(), String> {
+    for requirement in split_requirements(selector)? {
+        validate_label_requirement(requirement)
+            .map_err(|e| format!("label selector \"{}\": {}", requirement, e))?;
+    }
+    Ok(())
+}
+
+pub fn validate_field_selector(selector: &str) -> Result<(), String> {
+    for requirement in split_requirements(selector)? {
+        let (field, value) = split_operator(requirement, &["!=", "==", "="])
+            .ok_or_else(|| format!("field selector \"{}\": expected field=value, field==value or field!=value", requirement))?;
+        if !POD_SELECTABLE_FIELDS.contains(&field) {
+            return Err(format!("field selector \"{}\": {} is not selectable, use one of {}", requirement, field, POD_SELECTABLE_FIELDS.join(", ")));
+        }
+        if value.contains(['(', ')', ' ']) {
+            return Err(format!("field selector \"{}\": invalid value \"{}\"", requirement, value));
+        }
+    }
+    Ok(())
+}
+
+/// Renders a `LabelSelector` from a workload or service spec in the string form the api accepts.
+pub fn label_selector_string(selector: &LabelSelector) -> String {
+    let labels = selector.match_labels.iter()
+        .map(match_labels_string)
+        .filter(|l| !l.is_empty());
+    let expressions = selector.match_expressions.iter().flatten()
+        .map(|e| {
+            let values = e.values.as_deref().unwrap_or_default().join(",");
+            match e.operator.as_str() {
+                "In" => format!("{} in ({})", e.key, values),
+                "NotIn" => format!("{} notin ({})", e.key, values),
+                "DoesNotExist" => format!("!{}", e.key),
+                _ => e.key.clone(),
+            }
+        });
+    labels.chain(expressions).collect::<Vec<_>>().join(",")
+}
+
+/// `matchLabels` or a service selector as `key=value,...`.
+pub fn match_labels_string(labels: &BTreeMap<String, String>) -> String {
+    labels.iter()
+        .map(|(key, value)| format!("{}={}", key, value))
+        .collect::<Vec<_>>()
+        .join(",")
+}
+
+/// Splits on commas that are not inside an `in (...)` value list.
+fn split_requirements(selector: &str) -> Result<Vec<&str>, String> {
+    let mut requirements = Vec::new();
+    let mut depth = 0;
+    let mut start = 0;
+    for (i, c) in selector.char_indices() {
+        match c {
+            '(' => depth += 1,
+            ')' if depth == 0 => return Err(format!("unbalanced \")\" in \"{}\"", selector)),
+            ')' => depth -= 1,
+            ',' if depth == 0 => {
+                requirements.push(selector[start..i].trim());
+                start = i + 1;
+            }
+            _ => {}
+        }
+    }
+    if depth != 0 {
+        return Err(format!("unbalanced \"(\" in \"{}\"", selector));
+    }
+    requirements.push(selector[start..].trim());
+
+    if requirements.len() == 1 && requirements[0].is_empty() {
+        return Ok(vec![]);
+    }
+    if requirements.iter().any(|r| r.is_empty()) {
+        return Err(format!("empty requirement in \"{}\"", selector));
+    }
+    Ok(requirements)
+}
+
+fn validate_label_requirement(requirement: &str) -> Result<(), String> {
+    if let Some(key) = requirement.strip_prefix('!') {
+        return validate_label_key(key.trim());
+    }
+
+    if let Some((key, values)) = split_set_operator(requirement) {
+        validate_label_key(key)?;
+        let values = values.strip_prefix('(')
+            .and_then(|v| v.strip_suffix(')'))
+            .ok_or_else(|| "expected a value list like (a,b)".to_owned())?;
+        for value in values.split(',') {
+            validate_label_value(value.trim())?;
+        }
+        return Ok(());
+    }
+
+    match split_operator(requirement, &["!=", "==", "="]) {
+        Some((key, value)) => {
+            validate_label_key(key)?;
+            validate_label_value(value)
+        }
+        None => validate_label_key(requirement),
+    }
+}
+
+/// `key in (a,b)` or `key notin (a,b)`, the api also takes the list right after the operator as in `key in(a,b)`.
+fn split_set_operator(requirement: &str) -> Option<(&str, &str)> {
+    for operator in [" notin", " in"] {
+        let Some((key, values)) = requirement.split_once(operator) else {
+            continue;
+        };
+        if values.starts_with([' ', '(']) {
+            return Some((key.trim(), values.trim()));
+        }
+    }
+    None
+}
+
+fn split_operator<'a>(requirement: &'a str, operators: &[&str]) -> Option<(&'a str, &'a str)> {
+    operators.iter()
+        .find_map(|op| requirement.split_once(op))
+        .map(|(key, value)| (key.trim(), value.trim()))
+}
+
+/// `[prefix/]name`, prefix is a dns subdomain, name a qualified name of up to 63 characters.
+fn validate_label_key(key: &str) -> Result<(), String> {
+    let name = match key.split_once('/') {
+        Some((prefix, name)) => {
+            if prefix.is_empty() || prefix.len() > 253 || !prefix.split('.').all(is_dns_label) {
+                return Err(format!("invalid key prefix \"{}\"", prefix));
+            }
+            name
+        }
+        None => key,
+    };
+    if name.is_empty() || name.len() > 63 || !is_qualified(name) {
+        return Err(format!("invalid key \"{}\"", key));
+    }
+    Ok(())
+}
+
+fn validate_label_value(value: &str) -> Result<(), String> {
+    if value.is_empty() {
+        return Ok(());
+    }
+    if value.len() > 63 || !is_qualified(value) {
+        return Err(format!("invalid value \"{}\"", value));
+    }
+    Ok(())
+}
+
+fn is_qualified(name: &str) -> bool {
+    let starts_ends_alnum = name.chars().next().is_some_and(|c| c.is_ascii_alphanumeric())
+        && name.chars().last().is_some_and(|c| c.is_ascii_alphanumeric());
+    starts_ends_alnum && name.chars().all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.'))
+}
+
+fn is_dns_label(label: &str) -> bool {
+    !label.is_empty() && label.len() <= 63
+        && label.chars().all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '-')
+        && !label.starts_with('-') && !label.ends_with('-')
+}
+
+#[cfg(test)]
+mod tests {
+    use k8s_openapi::apimachinery::pkg::apis::meta::v1::LabelSelectorRequirement;
+    use super::*;
+
+    fn requirement(key: &str, operator: &str, values: Option<&[&str]>) -> LabelSelectorRequirement {
+        LabelSelectorRequirement {
+            key: key.to_owned(),
+            operator: operator.to_owned(),
+            values: values.map(|v| v.iter().map(|s| s.to_string()).collect()),
+        }
+    }
+
+    #[test]
+    fn renders_labels_then_expressions() {
+        let selector = LabelSelector {
+            match_labels: Some(BTreeMap::from([("tier".to_owned(), "web".to_owned()), ("app".to_owned(), "shop".to_owned())])),
+            match_expressions: Some(vec![
+                requirement("env", "In", Some(&["prod", "staging"])),
+                requirement("track", "NotIn", Some(&["canary"])),
+                requirement("legacy", "DoesNotExist", None),
+                requirement("team", "Exists", None),
+            ]),
+        };
+        let rendered = label_selector_string(&selector);
+        assert_eq!(rendered, "app=shop,tier=web,env in (prod,staging),track notin (canary),!legacy,team");
+        assert_eq!(validate_label_selector(&rendered), Ok(()));
+    }
+
+    #[test]
+    fn renders_empty_selectors_as_everything() {
+        assert_eq!(label_selector_string(&LabelSelector::default()), "");
+        let selector = LabelSelector { match_labels: Some(BTreeMap::new()), match_expressions: None };
+        assert_eq!(label_selector_string(&selector), "");
+        assert_eq!(match_labels_string(&BTreeMap::from([("app".to_owned(), "shop".to_owned())])), "app=shop");
+    }
+
+    #[test]
+    fn accepts_label_selectors() {
+        for selector in ["", "app", "!app", "app=web", "app==web", "app!=web", "app=", "example.com/app=web",
+            "app in (web, api),tier notin (db)", "app in(web,api)", "app notin(db)", "app in ()", " app = web , tier "] {
+            assert_eq!(validate_label_selector(selector), Ok(()), "{}", selector);
+        }
+    }
+
+    #[test]
+    fn rejects_label_selectors() {
+        for selector in ["app=web,", ",app", "app in (web", "app in web)", "app in web", "app in(web", "-app=web",
+            "app=web!", "Example.com/app", "/app", "a=b=c", &format!("app={}", "v".repeat(64))] {
+            assert!(validate_label_selector(selector).is_err(), "{}", selector);
+        }
+    }
+
+    #[test]
+    fn set_operator_needs_a_separated_word() {
+        assert_eq!(split_set_operator("app in(a,b)"), Some(("app", "(a,b)")));
+        assert_eq!(split_set_operator("app notin  (a)"), Some(("app", "(a)")));
+        assert_eq!(split_set_operator("app index"), None);
+        assert_eq!(split_set_operator("app=in"), None);
+    }
+
+    #[test]
+    fn field_selectors() {
+        assert_eq!(validate_field_selector("status.phase=Running,spec.nodeName!=node-1"), Ok(()));
+        assert_eq!(validate_field_selector("metadata.name==web"), Ok(()));
+        assert!(validate_field_selector("status.phase").is_err());
+        assert!(validate_field_selector("status.hostIP=10.0.0.1").is_err());
+        assert!(validate_field_selector("status.phase=Run ning").is_err());
+    }
+}
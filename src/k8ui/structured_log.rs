@@ -0,0 +1,430 @@
+//! JSON and logfmt log lines as fields, and filters over them like `level=error && user_id=42`.
+
+use std::cmp::Ordering;
+use regex::Regex;
+use serde_json::{Map, Value};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum LogFormat {
+    Json,
+    Logfmt,
+}
+
+/// The fields of a JSON object line, or of a logfmt line as strings.
+pub fn parse_fields(text: &str) -> Option<(LogFormat, Map<String, Value>)> {
+    let trimmed = text.trim();
+    if trimmed.starts_with('{') {
+        return match serde_json::from_str(trimmed) {
+            Ok(Value::Object(fields)) => Some((LogFormat::Json, fields)),
+            _ => None,
+        };
+    }
+    parse_logfmt(trimmed).map(|fields| (LogFormat::Logfmt, fields))
+}
+
+/// `ts=2025-01-01T10:00:00Z level=info msg="request done" status=200`.
+/// At least two pairs and nothing else, so plain text with an `=` in it isn't taken for logfmt.
+fn parse_logfmt(text: &str) -> Option<Map<String, Value>> {
+    let mut fields = Map::new();
+    let mut chars = text.chars().peekable();
+    loop {
+        while chars.next_if(|c| c.is_whitespace()).is_some() {}
+        if chars.peek().is_none() {
+            break;
+        }
+        let mut key = String::new();
+        while let Some(c) = chars.next_if(|c| !c.is_whitespace() && *c != '=') {
+            if c == '"' {
+                return None;
+            }
+            key.push(c);
+        }
+        chars.next_if_eq(&'=')?;
+        let mut value = String::new();
+        if chars.next_if_eq(&'"').is_some() {
+            loop {
+                match chars.next()? {
+                    '"' => break,
+                    '\\' => match chars.next()? {
+                        'n' => value.push('\n'),
+                        't' => value.push('\t'),
+                        c => value.push(c),
+                    },
+                    c => value.push(c),
+                }
+            }
+        } else {
+            while let Some(c) = chars.next_if(|c| !c.is_whitespace()) {
+                value.push(c);
+            }
+        }
+        fields.insert(key, Value::String(value));
+    }
+    (fields.len() >= 2).then_some(fields)
+}
+
+/// Looks up `a.b.c` in nested objects, a key containing dots is found as well.
+pub fn field<'a>(fields: &'a Map<String, Value>, path: &str) -> Option<&'a Value> {
+    if let Some(value) = fields.get(path) {
+        return Some(value);
+    }
+    let (first, rest) = path.split_once('.')?;
+    match fields.get(first)? {
+        Value::Object(nested) => field(nested, rest),
+        _ => None,
+    }
+}
+
+/// Strings without their quotes, everything else as JSON.
+pub fn value_text(value: &Value) -> String {
+    match value {
+        Value::String(text) => text.clone(),
+        value => value.to_string(),
+    }
+}
+
+/// Paths of the values in the object, nested objects are listed by their leaves.
+pub fn field_paths(fields: &Map<String, Value>, prefix: &str, paths: &mut Vec<String>) {
+    for (key, value) in fields {
+        let path = if prefix.is_empty() { key.clone() } else { format!("{}.{}", prefix, key) };
+        match value {
+            Value::Object(nested) if !nested.is_empty() => field_paths(nested, &path, paths),
+            _ => paths.push(path),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Op {
+    Eq,
+    Ne,
+    Matches,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+}
+
+#[derive(Debug, Clone)]
+enum Filter {
+    And(Box<Filter>, Box<Filter>),
+    Or(Box<Filter>, Box<Filter>),
+    Not(Box<Filter>),
+    /// Just a field name, true when the line has it.
+    Exists(String),
+    Compare(String, Op, String, Option<Regex>),
+}
+
+/// Compiled filter expression.
+/// `field=value`, `!=`, `~` for a regex, `<` `<=` `>` `>=` on numbers, a bare field checks it's there,
+/// combined with `&&`, `||`, `!` and parentheses. Strings compare ignoring case.
+#[derive(Debug, Clone)]
+pub struct FieldFilter(Filter);
+
+impl FieldFilter {
+    pub fn parse(expression: &str) -> Result<Self, String> {
+        let tokens = tokenize(expression)?;
+        let mut parser = Parser { tokens, pos: 0 };
+        let filter = parser.or()?;
+        match parser.tokens.get(parser.pos) {
+            None => Ok(Self(filter)),
+            Some(token) => Err(format!("Unexpected {}", token.describe())),
+        }
+    }
+
+    pub fn matches(&self, fields: &Map<String, Value>) -> bool {
+        self.0.matches(fields)
+    }
+}
+
+impl Filter {
+    fn matches(&self, fields: &Map<String, Value>) -> bool {
+        match self {
+            Filter::And(a, b) => a.matches(fields) && b.matches(fields),
+            Filter::Or(a, b) => a.matches(fields) || b.matches(fields),
+            Filter::Not(filter) => !filter.matches(fields),
+            Filter::Exists(path) => field(fields, path).is_some(),
+            Filter::Compare(path, op, expected, regex) => {
+                let Some(value) = field(fields, path) else {
+                    // a missing field is different from everything
+                    return *op == Op::Ne;
+                };
+                let actual = value_text(value);
+                let order = compare_numbers(&actual, expected);
+                match op {
+                    Op::Eq => order.map_or_else(|| actual.eq_ignore_ascii_case(expected), Ordering::is_eq),
+                    Op::Ne => order.map_or_else(|| !actual.eq_ignore_ascii_case(expected), Ordering::is_ne),
+                    Op::Matches => regex.as_ref().is_some_and(|regex| regex.is_match(&actual)),
+                    Op::Lt => order.is_some_and(Ordering::is_lt),
+                    Op::Le => order.is_some_and(Ordering::is_le),
+                    Op::Gt => order.is_some_and(Ordering::is_gt),
+                    Op::Ge => order.is_some_and(Ordering::is_ge),
+                }
+            }
+        }
+    }
+}
+
+/// Integers are compared exactly, ids beyond 2^53 would collide as floats, other numbers as floats.
+fn compare_numbers(actual: &str, expected: &str) -> Option<Ordering> {
+    if let (Ok(a), Ok(b)) = (actual.parse::<i128>(), expected.parse::<i128>()) {
+        return Some(a.cmp(&b));
+    }
+    actual.parse::<f64>().ok()?.partial_cmp(&expected.parse::<f64>().ok()?)
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Word(String),
+    Op(Op),
+    And,
+    Or,
+    Not,
+    Open,
+    Close,
+}
+
+impl Token {
+    fn describe(&self) -> String {
+        match self {
+            Token::Word(word) => format!("'{}'", word),
+            Token::Op(_) => "comparison".to_owned(),
+            Token::And => "'&&'".to_owned(),
+            Token::Or => "'||'".to_owned(),
+            Token::Not => "'!'".to_owned(),
+            Token::Open => "'('".to_owned(),
+            Token::Close => "')'".to_owned(),
+        }
+    }
+}
+
+fn tokenize(expression: &str) -> Result<Vec<Token>, String> {
+    let mut tokens = Vec::new();
+    let mut chars = expression.chars().peekable();
+    while let Some(c) = chars.next() {
+        let token = match c {
+            c if c.is_whitespace() => continue,
+            '(' => Token::Open,
+            ')' => Token::Close,
+            '&' if chars.next_if_eq(&'&').is_some() => Token::And,
+            '|' if chars.next_if_eq(&'|').is_some() => Token::Or,
+            '!' if chars.next_if_eq(&'=').is_some() => Token::Op(Op::Ne),
+            '!' => Token::Not,
+            '=' => {
+                chars.next_if_eq(&'=');
+                Token::Op(Op::Eq)
+            }
+            '~' => Token::Op(Op::Matches),
+            '<' if chars.next_if_eq(&'=').is_some() => Token::Op(Op::Le),
+            '<' => Token::Op(Op::Lt),
+            '>' if chars.next_if_eq(&'=').is_some() => Token::Op(Op::Ge),
+            '>' => Token::Op(Op::Gt),
+            quote @ ('"' | '\'') => {
+                let mut word = String::new();
+                loop {
+                    match chars.next() {
+                        Some(c) if c == quote => break,
+                        Some('\\') => word.extend(chars.next()),
+                        Some(c) => word.push(c),
+                        None => return Err("Unclosed quote".to_owned()),
+                    }
+                }
+                Token::Word(word)
+            }
+            c => {
+                let mut word = c.to_string();
+                while let Some(c) = chars.next_if(|c| !c.is_whitespace() && !"()&|!=~<>\"'".contains(*c)) {
+                    word.push(c);
+                }
+                Token::Word(word)
+            }
+        };
+        tokens.push(token);
+    }
+    Ok(tokens)
+}
+
+struct Parser {
+    tokens: Vec<Token>,
+    pos: usize,
+}
+
+impl Parser {
+    fn next(&mut self) -> Option<Token> {
+        let token = self.tokens.get(self.pos).cloned();
+        self.pos += 1;
+        token
+    }
+
+    fn next_if(&mut self, token: &Token) -> bool {
+        let found = self.tokens.get(self.pos) == Some(token);
+        if found {
+            self.pos += 1;
+        }
+        found
+    }
+
+    fn or(&mut self) -> Result<Filter, String> {
+        let mut filter = self.and()?;
+        while self.next_if(&Token::Or) {
+            filter = Filter::Or(Box::new(filter), Box::new(self.and()?));
+        }
+        Ok(filter)
+    }
+
+    fn and(&mut self) -> Result<Filter, String> {
+        let mut filter = self.unary()?;
+        while self.next_if(&Token::And) {
+            filter = Filter::And(Box::new(filter), Box::new(self.unary()?));
+        }
+        Ok(filter)
+    }
+
+    fn unary(&mut self) -> Result<Filter, String> {
+        match self.next() {
+            Some(Token::Not) => Ok(Filter::Not(Box::new(self.unary()?))),
+            Some(Token::Open) => {
+                let filter = self.or()?;
+                match self.next() {
+                    Some(Token::Close) => Ok(filter),
+                    _ => Err("Missing ')'".to_owned()),
+                }
+            }
+            Some(Token::Word(path)) => {
+                let Some(Token::Op(op)) = self.tokens.get(self.pos).cloned() else {
+                    return Ok(Filter::Exists(path));
+                };
+                self.pos += 1;
+                let Some(Token::Word(value)) = self.next() else {
+                    return Err(format!("Missing value after {}", path));
+                };
+                let regex = match op {
+                    Op::Matches => Some(Regex::new(&value).map_err(|e| e.to_string())?),
+                    _ => None,
+                };
+                Ok(Filter::Compare(path, op, value, regex))
+            }
+            Some(token) => Err(format!("Unexpected {}", token.describe())),
+            None => Err("Incomplete filter".to_owned()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+    use super::*;
+
+    fn fields(value: Value) -> Map<String, Value> {
+        match value {
+            Value::Object(fields) => fields,
+            _ => panic!("not an object"),
+        }
+    }
+
+    fn matches(expression: &str, value: Value) -> bool {
+        FieldFilter::parse(expression).unwrap().matches(&fields(value))
+    }
+
+    #[test]
+    fn and_binds_tighter_than_or() {
+        // a || (b && c)
+        assert!(matches("a || b && c", json!({"a": 1})));
+        assert!(!matches("a || b && c", json!({"b": 1})));
+        assert!(matches("a || b && c", json!({"b": 1, "c": 1})));
+        // (a || b) && c
+        assert!(!matches("(a || b) && c", json!({"a": 1})));
+    }
+
+    #[test]
+    fn not_applies_to_the_next_term() {
+        assert!(matches("!a && b", json!({"b": 1})));
+        assert!(!matches("!a && b", json!({"a": 1, "b": 1})));
+        assert!(!matches("!(a || b)", json!({"b": 1})));
+        assert!(matches("!!a", json!({"a": 1})));
+    }
+
+    #[test]
+    fn quoted_values_keep_spaces_and_escapes() {
+        assert!(matches(r#"msg="request \"done\"""#, json!({"msg": "request \"done\""})));
+        assert!(matches("msg='a && b'", json!({"msg": "a && b"})));
+        assert!(matches(r"path='C:\\tmp'", json!({"path": r"C:\tmp"})));
+        assert_eq!(FieldFilter::parse("msg=\"open").unwrap_err(), "Unclosed quote");
+    }
+
+    #[test]
+    fn missing_field_only_matches_not_equal() {
+        assert!(matches("user!=bob", json!({})));
+        assert!(!matches("user=bob", json!({})));
+        assert!(!matches("user<5", json!({})));
+        assert!(matches("!(user=bob)", json!({})));
+    }
+
+    #[test]
+    fn strings_compare_ignoring_case() {
+        assert!(matches("level=ERROR", json!({"level": "error"})));
+        assert!(matches("level==error", json!({"level": "error"})));
+        assert!(!matches("level!=Error", json!({"level": "error"})));
+        assert!(matches("msg~^req", json!({"msg": "request done"})));
+    }
+
+    #[test]
+    fn integers_beyond_f64_precision_compare_exactly() {
+        let line = json!({"id": 9007199254740993u64});
+        assert!(matches("id=9007199254740993", line.clone()));
+        assert!(!matches("id=9007199254740992", line.clone()));
+        assert!(matches("id!=9007199254740992", line.clone()));
+        assert!(matches("id>9007199254740992", line.clone()));
+        assert!(matches("id<=18446744073709551615", line));
+    }
+
+    #[test]
+    fn other_numbers_compare_as_floats() {
+        assert!(matches("latency<1.5", json!({"latency": 1.25})));
+        assert!(matches("status=200.0", json!({"status": 200})));
+        assert!(matches("status>=2e2", json!({"status": "200"})));
+        // no number, no order
+        assert!(!matches("status<300", json!({"status": "ok"})));
+    }
+
+    #[test]
+    fn nested_fields_by_path() {
+        assert!(matches("http.status=500", json!({"http": {"status": 500}})));
+        assert!(matches("k8s.pod=web", json!({"k8s.pod": "web"})));
+    }
+
+    #[test]
+    fn malformed_expressions_are_rejected() {
+        assert_eq!(FieldFilter::parse("(a").unwrap_err(), "Missing ')'");
+        assert_eq!(FieldFilter::parse("a=").unwrap_err(), "Missing value after a");
+        assert_eq!(FieldFilter::parse("a &&").unwrap_err(), "Incomplete filter");
+        assert_eq!(FieldFilter::parse("a b").unwrap_err(), "Unexpected 'b'");
+        assert!(FieldFilter::parse("msg~(").is_err());
+    }
+
+    #[test]
+    fn logfmt_lines_become_fields() {
+        let (format, fields) = parse_fields(r#"ts=2025-01-01T10:00:00Z level=info msg="request \"done\"\n" status=200"#).unwrap();
+        assert_eq!(format, LogFormat::Logfmt);
+        assert_eq!(fields["level"], "info");
+        assert_eq!(fields["msg"], "request \"done\"\n");
+        assert_eq!(fields["status"], "200");
+        assert_eq!(parse_fields("a= b=").unwrap().1["a"], "");
+    }
+
+    #[test]
+    fn plain_text_is_not_logfmt() {
+        assert!(parse_fields("server started").is_none());
+        assert!(parse_fields("user logged in with id=5").is_none());
+        assert!(parse_fields("only=one").is_none());
+        assert!(parse_fields(r#"a=1 b="unclosed"#).is_none());
+        assert!(parse_fields(r#"a"b=1 c=2"#).is_none());
+    }
+
+    #[test]
+    fn json_objects_only() {
+        assert_eq!(parse_fields(r#" {"level":"warn"} "#).unwrap().0, LogFormat::Json);
+        assert!(parse_fields("{not json").is_none());
+        assert!(parse_fields("[1, 2]").is_none());
+    }
+}
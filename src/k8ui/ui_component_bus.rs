@@ -1,3 +1,5 @@
+use crate::k8ui::appstate::PodKey;
+
 #[derive(Debug)]
 pub enum UiBus {
     Action(UiAction),
@@ -5,5 +7,5 @@ pub enum UiBus {
 
 #[derive(Debug)]
 pub enum UiAction {
-    PinColumn(String),
+    PinColumn(PodKey),
 }
\ No newline at end of file
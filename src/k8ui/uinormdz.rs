@@ -1,26 +1,40 @@
 use std::cell::RefCell;
-use std::collections::{BTreeMap, HashSet};
-use std::convert::TryFrom;
+use std::collections::{BTreeMap, HashMap, HashSet};
 use std::rc::Rc;
-use crossbeam::channel::{bounded, Receiver, Sender, TryRecvError};
+use std::time::Duration;
+use crossbeam::channel::{bounded, unbounded, Receiver, Sender, TryRecvError};
+use k8s_openapi::chrono::Utc;
 use eframe::{egui};
-use eframe::egui::{Align, CentralPanel, Context, Layout, RichText, ScrollArea, SidePanel, TextBuffer, Ui};
-use crate::k8ui::appstate::ShortKContainer;
+use eframe::egui::{Align, CentralPanel, CollapsingHeader, Color32, ComboBox, Context, Key, Layout, RichText, ScrollArea, SidePanel, TextEdit, Ui};
+use crate::k8ui::appstate::{ForwardId, ForwardInfo, KubeconfigInfo, LogRequestId, LogTarget, PodKey, PodQuery, ShortKPod, WorkloadInfo, WorkloadKey};
 use crate::k8ui::components::container_column::ContainerColumn;
 use crate::k8ui::components::log_window::LogWindow;
+use crate::k8ui::components::namespace_picker::NamespacePicker;
+use crate::k8ui::components::service_list::ServiceList;
+use crate::k8ui::k8api::{default_kubeconfig_paths, join_kubeconfig_paths};
+use crate::k8ui::name_match::{NameMatch, NameMatcher, NameMatchMode};
+use crate::k8ui::selectors::POD_SELECTABLE_FIELDS;
 use crate::k8ui::my_thread::{ApiCommand, ApiThread, ThreadMessage, UIData};
 use crate::k8ui::my_thread::ThreadMessage::Api;
 use crate::k8ui::ui_component_bus::{UiAction, UiBus};
 
 pub const UNIFIED_HEIGHT: f32 = 800.0;
+pub const DEFAULT_SIDECAR_PATTERNS: &str = "istio-*,linkerd-*";
+/// Per forward, older routing events are dropped.
+const MAX_FORWARD_EVENTS: usize = 20;
 
 pub fn run_ui() -> Result<(), eframe::Error> {
     let (thread_sender, thread_receiver) = bounded(5);
-    let (ui_sender, ui_receiver) = bounded(5);
+    let (ui_sender, ui_receiver) = unbounded();
     let (state_upstream_sender, state_upstream_receiver) = bounded(5);
 
     ApiThread::new(thread_receiver, ui_sender);
 
+    let conf_file_path = join_kubeconfig_paths(&default_kubeconfig_paths());
+    if let Err(e) = thread_sender.try_send(Api(ApiCommand::ReloadClientWithConfig(conf_file_path.clone()))) {
+        println!("err send {}", e);
+    }
+
     let options = eframe::NativeOptions {
         viewport: egui::ViewportBuilder::default()
             .with_inner_size([1200.0, UNIFIED_HEIGHT])
@@ -28,9 +42,8 @@ pub fn run_ui() -> Result<(), eframe::Error> {
         ..Default::default()
     };
 
-    let conf_file_path = "aaa".to_string();
-    let namespace = "xxx".to_string();
-    let filter_pod_prefix = "yyy".to_string();
+    let namespace = "default".to_string();
+    let filter_pod_prefix = "".to_string();
 
     let app = DemoApp::new(Some(conf_file_path), Some(namespace), Some(filter_pod_prefix),
                            thread_sender, ui_receiver, state_upstream_receiver, state_upstream_sender);
@@ -57,10 +70,30 @@ impl AppStateWrapper {
     }
 }
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum MainView {
+    Pods,
+    Services,
+}
+
+/// Columns with where their pod name matched the filter, best match first.
+type RankedColumns<'a> = Vec<(NameMatch, &'a mut ContainerColumn)>;
+
 pub struct DemoApp {
     conf_file_path: Option<String>,
-    namespace: Option<String>,
-    filter_pod_prefix: Option<String>,
+    namespace_picker: NamespacePicker,
+    pod_query: PodQuery,
+    query_error: Option<String>,
+    /// Built from the query the watch was started with, used for ranking and highlighting.
+    name_matcher: NameMatcher,
+    /// Comma separated globs, matching containers are collapsed in the columns.
+    sidecar_patterns: String,
+    sidecar_matchers: Vec<NameMatcher>,
+    group_by_owner: bool,
+    view: MainView,
+    service_list: ServiceList,
+    workloads: HashMap<WorkloadKey, WorkloadInfo>,
+    forwards: BTreeMap<ForwardId, ForwardInfo>,
 
     thread_sender: Sender<ThreadMessage>,
     ui_receiver: Receiver<ThreadMessage>,
@@ -68,10 +101,14 @@ pub struct DemoApp {
     state_upstream_receiver: Receiver<UiBus>,
     state_upstream_sender: Sender<UiBus>,
 
-    pods: Vec<Rc<ShortKContainer>>,
-    pinned: BTreeMap<String, ContainerColumn>,
+    kubeconfig: KubeconfigInfo,
+    current_context: Option<String>,
+    last_error: Option<String>,
+
+    pinned: BTreeMap<PodKey, ContainerColumn>,
 
-    my_windows: Vec<LogWindow>,
+    /// Logs of several pods, windows of a single pod belong to its column.
+    log_windows: Vec<LogWindow>,
     container_columns: Option<Vec<ContainerColumn>>,
     open: HashSet<String>,
 }
@@ -80,22 +117,35 @@ impl DemoApp {
     pub fn new(conf_file_path: Option<String>, namespace: Option<String>, filter_pod_prefix: Option<String>,
                thread_sender: Sender<ThreadMessage>, ui_receiver: Receiver<ThreadMessage>,
                state_upstream_receiver: Receiver<UiBus>, state_upstream_sender: Sender<UiBus>, ) -> Self {
-        let mut open = HashSet::new();
+        let open = HashSet::new();
 
         Self {
             conf_file_path,
-            namespace,
-            filter_pod_prefix,
+            namespace_picker: NamespacePicker::new(namespace.unwrap_or_default()),
+            pod_query: PodQuery {
+                name_filter: filter_pod_prefix.unwrap_or_default(),
+                ..PodQuery::default()
+            },
+            query_error: None,
+            name_matcher: NameMatcher::Any,
+            sidecar_patterns: DEFAULT_SIDECAR_PATTERNS.to_owned(),
+            sidecar_matchers: sidecar_matchers(DEFAULT_SIDECAR_PATTERNS),
+            group_by_owner: true,
+            view: MainView::Pods,
+            service_list: ServiceList::new(thread_sender.clone()),
+            workloads: HashMap::new(),
+            forwards: BTreeMap::new(),
 
             thread_sender,
             ui_receiver,
             state_upstream_receiver,
             state_upstream_sender,
 
-            pods: vec![],
+            kubeconfig: KubeconfigInfo::default(),
+            current_context: None,
+            last_error: None,
 
-            //test
-            my_windows: vec![LogWindow::new("panels".to_owned())],
+            log_windows: Vec::new(),
             container_columns: None,
             open,
             pinned: BTreeMap::new(),
@@ -103,44 +153,161 @@ impl DemoApp {
     }
 
     pub fn draw_pinned_right_panel(&mut self, ui: &mut Ui) {
-        for (name, _) in self.pinned.iter() {
-            ui.toggle_value(&mut false, name);
+        for (_, col) in self.pinned.iter() {
+            ui.toggle_value(&mut false, col.name.as_str());
+        }
+    }
+
+    pub fn draw_forwards(&mut self, ui: &mut Ui) {
+        if self.forwards.is_empty() {
+            ui.label(RichText::new("None").weak());
+            return;
+        }
+
+        let stats = self.forwards.values().map(|f| &f.stats);
+        let (up, down, up_rate, down_rate, open) = stats.fold((0, 0, 0.0, 0.0, 0), |t, s| {
+            (t.0 + s.traffic.bytes_up, t.1 + s.traffic.bytes_down, t.2 + s.up_rate, t.3 + s.down_rate, t.4 + s.traffic.open)
+        });
+        ui.label(format!("{} open  ↑ {} ({}/s)  ↓ {} ({}/s)", open, format_bytes(up as f64), format_bytes(up_rate), format_bytes(down as f64), format_bytes(down_rate)));
+        ui.separator();
+
+        for forward in self.forwards.values() {
+            ui.label(forward.target.to_string());
+            ui.horizontal(|ui| {
+                ui.label(format!(":{} →", forward.remote_port));
+                ui.hyperlink_to(forward.local_addr.to_string(), forward.url());
+                if forward.pooled {
+                    ui.label(RichText::new("pooled").weak());
+                }
+                if ui.small_button("Stop").clicked() {
+                    match self.thread_sender.try_send(ThreadMessage::Api(ApiCommand::StopPortForward(forward.id))) {
+                        Ok(_) => println!("ok send"),
+                        Err(_) => println!("err send"),
+                    };
+                }
+            });
+            if let Some(pod) = forward.events.iter().rev().find_map(|e| e.routed_to.as_ref()) {
+                ui.label(RichText::new(format!("via {}", pod.name)).weak());
+            }
+            if let Some(last) = forward.events.last() {
+                let events = forward.events.iter()
+                    .map(|e| format!("{} {}", e.at.format("%H:%M:%S"), e.message))
+                    .collect::<Vec<_>>()
+                    .join("\n");
+                ui.label(RichText::new(&last.message).small().weak()).on_hover_text(events);
+            }
+
+            let stats = &forward.stats;
+            ui.label(RichText::new(format!("{} open, {} total  ↑ {} ({}/s)  ↓ {} ({}/s)",
+                                           stats.traffic.open, stats.traffic.accepted,
+                                           format_bytes(stats.traffic.bytes_up as f64), format_bytes(stats.up_rate),
+                                           format_bytes(stats.traffic.bytes_down as f64), format_bytes(stats.down_rate))).small());
+            CollapsingHeader::new(format!("Connections ({})", stats.connections.len()))
+                .id_salt((forward.id, "connections"))
+                .show(ui, |ui| {
+                    for connection in stats.connections.iter().rev() {
+                        let duration = connection.closed.unwrap_or_else(Utc::now) - connection.opened;
+                        let mut text = format!("{} {}  {}s", connection.opened.format("%H:%M:%S"), connection.peer, duration.num_seconds());
+                        if connection.closed.is_some() {
+                            text = format!("{}  ↑ {}  ↓ {}", text, format_bytes(connection.bytes_up as f64), format_bytes(connection.bytes_down as f64));
+                        } else {
+                            text = format!("{}  open", text);
+                        }
+                        match &connection.error {
+                            Some(error) => ui.colored_label(ui.visuals().error_fg_color, RichText::new(text).small()).on_hover_text(error),
+                            None => ui.label(RichText::new(text).small()),
+                        };
+                    }
+                });
+            ui.separator();
         }
     }
 
     pub fn draw_checkboxes(&mut self, ui: &mut Ui) {
-        let Self { my_windows, open, pods, .. } = self;
-        for demo in my_windows {
-            let mut is_open = open.contains(demo.name.as_str());
-            ui.toggle_value(&mut is_open, demo.name.clone());
-            set_open(open, demo.name.as_str(), is_open);
+        let Self { log_windows, container_columns, thread_sender, .. } = self;
+        for window in log_windows {
+            let mut is_open = window.opened;
+            if ui.toggle_value(&mut is_open, window.name.clone()).changed() {
+                window.toggle(thread_sender);
+            }
         }
-        for pod in pods {
-            ui.toggle_value(&mut true, pod.pod_name.clone());
+        for col in container_columns.iter().flatten() {
+            ui.toggle_value(&mut true, col.name.clone());
         }
     }
 
 
     pub fn redraw_windows_based_on_visibility(&mut self, ctx: &Context) {
-        let Self { my_windows, open, .. } = self;
-        for win in my_windows {
-            let mut is_open = open.contains(win.name.as_str());
-            win.draw(ctx, &mut is_open);
-            set_open(open, win.name.as_str(), is_open);
+        let Self { log_windows, thread_sender, .. } = self;
+        for window in log_windows {
+            // aggregated windows read every container unless one is typed into the query
+            window.show(ctx, &[], thread_sender);
+        }
+    }
+
+    /// Shows the window tailing `target`, reusing the one opened for it before.
+    fn open_log_window(&mut self, target: LogTarget) {
+        let index = match self.log_windows.iter().position(|w| w.target == target) {
+            Some(index) => index,
+            None => {
+                self.log_windows.push(LogWindow::new(format!("{} Logs", target), target, None));
+                self.log_windows.len() - 1
+            }
+        };
+        let window = &mut self.log_windows[index];
+        if !window.opened {
+            window.toggle(&self.thread_sender);
         }
     }
 
-    pub fn toggle_window(&mut self, ctx: &Context, win_name: &str) {
+    pub fn toggle_window(&mut self, win_name: &str) {
         let Self { open, .. } = self;
-        let mut is_open = open.contains(win_name);
+        let is_open = open.contains(win_name);
         set_open(open, win_name.to_owned().as_str(), !is_open);
     }
 
     pub fn redraw_columns(&mut self, ctx: &Context, ui: &mut Ui) {
-        if let Some(columns) = &mut self.container_columns {
-            for x in columns.iter_mut() {
-                x.draw(ctx, ui);
-                ui.separator();
+        let Self { container_columns, pinned, name_matcher, sidecar_matchers, group_by_owner, workloads, forwards, .. } = self;
+        let mut tail_workload = None;
+        if let Some(columns) = container_columns {
+            let mut ranked: RankedColumns = columns.iter_mut()
+                .map(|col| (name_matcher.find(col.name.as_str()).unwrap_or_default(), col))
+                .collect();
+            // stable, equal scores keep the namespace/name order
+            ranked.sort_by_key(|(found, _)| -found.score);
+
+            if *group_by_owner {
+                // groups keep the rank of their best pod
+                let mut groups: Vec<(Option<WorkloadKey>, RankedColumns)> = Vec::new();
+                for (found, col) in ranked {
+                    let owner = col.pod.owner.clone();
+                    match groups.iter_mut().find(|(key, _)| *key == owner) {
+                        Some((_, group)) => group.push((found, col)),
+                        None => groups.push((owner, vec![(found, col)])),
+                    }
+                }
+                // standalone pods last
+                groups.sort_by_key(|(key, _)| key.is_none());
+
+                ui.vertical(|ui| {
+                    for (key, group) in groups {
+                        if draw_workload_heading(ui, key.as_ref(), key.as_ref().and_then(|k| workloads.get(k)), group.len()) {
+                            tail_workload = key.clone();
+                        }
+                        ui.horizontal(|ui| {
+                            for (found, x) in group {
+                                x.draw(ctx, ui, &found.ranges, sidecar_matchers, forwards);
+                                ui.separator();
+                            }
+                        });
+                        ui.separator();
+                    }
+                });
+            } else {
+                for (found, x) in ranked {
+                    x.draw(ctx, ui, &found.ranges, sidecar_matchers, forwards);
+                    ui.separator();
+                }
             }
         } else {
             ui.spinner();
@@ -148,23 +315,264 @@ impl DemoApp {
 
         ui.separator();
 
-        for (_, col) in self.pinned.iter_mut() {
-            col.draw(ctx, ui);
+        for (_, col) in pinned.iter_mut() {
+            let found = name_matcher.find(col.name.as_str()).unwrap_or_default();
+            col.draw(ctx, ui, &found.ranges, sidecar_matchers, forwards);
             ui.separator();
         }
+
+        if let Some(key) = tail_workload {
+            self.open_log_window(LogTarget::Workload(key));
+        }
+    }
+
+    pub fn draw_context_picker(&mut self, ui: &mut Ui) {
+        let mut selected = self.current_context.clone();
+        let selected_text = selected.clone().unwrap_or_else(|| "No context".to_owned());
+
+        ComboBox::from_label("Context")
+            .selected_text(selected_text)
+            .width(250.0)
+            .show_ui(ui, |ui| {
+                for context in self.kubeconfig.contexts.iter() {
+                    let response = ui.selectable_value(&mut selected, Some(context.name.clone()), context.describe());
+                    if let Some(source) = &context.source {
+                        response.on_hover_text(source.display().to_string());
+                    }
+                }
+            });
+
+        if selected != self.current_context {
+            if let Some(name) = selected.as_ref() {
+                self.switch_context(name.clone());
+            }
+            self.current_context = selected;
+        }
+    }
+
+    pub fn draw_kubeconfig_sources(&mut self, ui: &mut Ui) {
+        let sources = &self.kubeconfig.sources;
+        if sources.is_empty() {
+            return;
+        }
+
+        CollapsingHeader::new(format!("Merged kubeconfig files ({})", sources.len()))
+            .id_salt("kubeconfig_sources")
+            .show(ui, |ui| {
+                for source in sources {
+                    let path = source.path.display().to_string();
+                    if let Some(error) = &source.error {
+                        ui.colored_label(Color32::RED, format!("{}: {}", path, error));
+                        continue;
+                    }
+                    ui.label(RichText::new(path).strong());
+                    ui.label(format!("contexts: {}", source.contexts.join(", ")));
+                    ui.label(format!("clusters: {}", source.clusters.join(", ")));
+                    ui.label(format!("users: {}", source.users.join(", ")));
+                }
+            });
+    }
+
+    fn switch_context(&mut self, name: String) {
+        let context_namespace = self.kubeconfig.contexts.iter()
+            .find(|c| c.name == name)
+            .and_then(|c| c.namespace.clone());
+        if let Some(namespace) = context_namespace {
+            self.namespace_picker.selected = namespace;
+        }
+
+        match self.thread_sender.try_send(ThreadMessage::Api(ApiCommand::SwitchContext(name))) {
+            Ok(_) => println!("ok send"),
+            Err(_) => println!("err send"),
+        };
+
+        self.last_error = None;
+        self.container_columns = None;
+        self.workloads.clear();
+        self.watch_pods();
+    }
+
+    /// Rebuilds the apis for the selected namespace and restarts the pod watch on them.
+    /// Invalid selectors keep the current watch running and only show the error.
+    fn watch_pods(&mut self) {
+        self.query_error = self.pod_query.validate().err();
+        if self.query_error.is_some() {
+            return;
+        }
+        self.name_matcher = self.pod_query.name_matcher().unwrap_or_default();
+
+        match self.thread_sender.try_send(ThreadMessage::Api(ApiCommand::ReloadApisWithNameSpace(self.namespace_picker.scope()))) {
+            Ok(_) => println!("ok send"),
+            Err(_) => println!("err send"),
+        };
+
+        match self.thread_sender.try_send(ThreadMessage::Api(ApiCommand::PullPods(self.pod_query.clone()))) {
+            Ok(_) => println!("ok send"),
+            Err(_) => println!("err send"),
+        };
+
+        if self.view == MainView::Services {
+            self.pull_services();
+        }
+    }
+
+    /// Services are listed once per namespace, not watched.
+    fn pull_services(&mut self) {
+        self.service_list.services = None;
+        match self.thread_sender.try_send(ThreadMessage::Api(ApiCommand::PullServices)) {
+            Ok(_) => println!("ok send"),
+            Err(_) => println!("err send"),
+        };
+    }
+
+    fn handle_data(&mut self, data: UIData) {
+        match data {
+            UIData::Pods(new_pods) => self.replace_pods(new_pods),
+            UIData::PodUpdated(pod) => self.update_pod(pod),
+            UIData::Workloads(workloads) => {
+                for info in workloads {
+                    self.workloads.insert(info.key.clone(), info);
+                }
+            }
+            UIData::PodDeleted(key) => {
+                if let Some(col) = self.pinned.get_mut(&key) {
+                    col.deleted = true;
+                }
+                if let Some(columns) = &mut self.container_columns {
+                    columns.retain(|col| col.key != key);
+                }
+            }
+            // data of requests that were replaced or whose column is gone has no window anymore
+            UIData::Logs(id, lines) => {
+                if let Some(window) = self.log_window_mut(id) {
+                    window.push_lines(lines);
+                }
+            }
+            UIData::LogSources(id, sources) => {
+                if let Some(window) = self.log_window_mut(id) {
+                    window.set_sources(sources);
+                }
+            }
+            UIData::LogEnded(id, error) => {
+                if let Some(window) = self.log_window_mut(id) {
+                    window.request_ended(error);
+                }
+            }
+            UIData::Services(services) => self.service_list.services = Some(services),
+            UIData::ForwardStarted(info) => {
+                self.forwards.insert(info.id, info);
+            }
+            UIData::ForwardEvent(id, event) => {
+                if let Some(forward) = self.forwards.get_mut(&id) {
+                    if forward.events.len() >= MAX_FORWARD_EVENTS {
+                        forward.events.remove(0);
+                    }
+                    forward.events.push(event);
+                }
+            }
+            UIData::ForwardTraffic(id, traffic) => {
+                if let Some(forward) = self.forwards.get_mut(&id) {
+                    forward.stats.sample(traffic);
+                }
+            }
+            UIData::ForwardConnection(id, connection) => {
+                if let Some(forward) = self.forwards.get_mut(&id) {
+                    forward.stats.connection(connection);
+                }
+            }
+            UIData::ForwardStopped(id, error) => {
+                self.forwards.remove(&id);
+                if error.is_some() {
+                    self.last_error = error;
+                }
+            }
+            UIData::Kubeconfig(info) => {
+                self.current_context = info.current_context.clone();
+                let context_namespace = info.contexts.iter()
+                    .find(|c| Some(&c.name) == info.current_context.as_ref())
+                    .and_then(|c| c.namespace.clone());
+                if let Some(namespace) = context_namespace {
+                    self.namespace_picker.selected = namespace;
+                }
+                self.kubeconfig = info;
+                self.last_error = None;
+            }
+            UIData::Namespaces(namespaces) => self.namespace_picker.namespaces = namespaces,
+            UIData::Error(error) => self.last_error = Some(error),
+        }
+    }
+
+    /// Applies a full pod list while keeping existing columns (and their open windows) in place.
+    fn replace_pods(&mut self, new_pods: Vec<ShortKPod>) {
+        let mut previous: HashMap<PodKey, ContainerColumn> = self.container_columns.take()
+            .unwrap_or_default()
+            .into_iter()
+            .map(|col| (col.key.clone(), col))
+            .collect();
+        for col in self.pinned.values_mut() {
+            col.deleted = true;
+        }
+
+        let mut columns = Vec::new();
+        for pod in new_pods {
+            let key = pod.key();
+            if let Some(col) = self.pinned.get_mut(&key) {
+                col.update(Rc::new(pod));
+                continue;
+            }
+            match previous.remove(&key) {
+                Some(mut col) => {
+                    col.update(Rc::new(pod));
+                    columns.push(col);
+                }
+                None => columns.push(self.new_column(pod)),
+            }
+        }
+        self.container_columns = Some(columns);
+    }
+
+    fn update_pod(&mut self, pod: ShortKPod) {
+        let key = pod.key();
+        if let Some(col) = self.pinned.get_mut(&key) {
+            col.update(Rc::new(pod));
+            return;
+        }
+
+        let position = self.container_columns.get_or_insert_with(Vec::new)
+            .binary_search_by(|col| col.key.cmp(&key));
+        match position {
+            Ok(i) => self.container_columns.as_mut().unwrap()[i].update(Rc::new(pod)),
+            Err(i) => {
+                let column = self.new_column(pod);
+                self.container_columns.as_mut().unwrap().insert(i, column);
+            }
+        }
+    }
+
+    fn log_window_mut(&mut self, id: LogRequestId) -> Option<&mut LogWindow> {
+        if let Some(index) = self.log_windows.iter().position(|w| w.request == Some(id)) {
+            return Some(&mut self.log_windows[index]);
+        }
+        self.pinned.values_mut()
+            .chain(self.container_columns.iter_mut().flatten())
+            .find_map(|col| col.log_window_mut(id))
+    }
+
+    fn new_column(&self, pod: ShortKPod) -> ContainerColumn {
+        ContainerColumn::new(Rc::new(pod), self.thread_sender.clone(), self.state_upstream_sender.clone())
     }
 
     pub fn update_state(&mut self, ctx: &Context, _frame: &mut eframe::Frame) {
         match self.state_upstream_receiver.try_recv() {
             Ok(msg) => match msg {
                 UiBus::Action(action) => match action {
-                    UiAction::PinColumn(col_name) => {
+                    UiAction::PinColumn(col_key) => {
                         if let Some(columns) = &mut self.container_columns {
                             for (i, col) in columns.iter_mut().enumerate() {
-                                if col.name == col_name {
+                                if col.key == col_key {
                                     let removed = columns.remove(i);
                                     // self.pinned.insert(0, removed);
-                                    self.pinned.insert(removed.name.clone(), removed);
+                                    self.pinned.insert(removed.key.clone(), removed);
                                     break;
                                 }
                             }
@@ -181,44 +589,27 @@ impl DemoApp {
         }
 
 
-        match self.ui_receiver.try_recv() {
-            Ok(msg) => match msg {
-                Api(_) => println!("This shouldn't happen on ui"),
-
-                ThreadMessage::Data(data) => match data {
-                    UIData::Pods(new_pods) => {
-
-                        self.pods = new_pods.into_iter()
-                            .filter(|p| !self.pinned.contains_key(p.pod_name.as_str()))
-                            .map(|data| Rc::new(data))
-                            .collect();
-                        self.container_columns = Some(self.pods.iter()
-                            .map(|p| ContainerColumn::new(
-                                Rc::clone(p),
-                                self.thread_sender.clone(),
-                                self.state_upstream_sender.clone()))
-                            .collect());
-                    }
-                    UIData::Logs(lines) => {
-                        if let Some(columns) = &mut self.container_columns {
-                            for col in columns.iter_mut() {
-                                col.log_text = Some(lines.join("\n"));
-                            }
-                        }
-                    }
+        loop {
+            match self.ui_receiver.try_recv() {
+                Ok(msg) => match msg {
+                    Api(_) => println!("This shouldn't happen on ui"),
+                    ThreadMessage::Data(data) => self.handle_data(data),
                 }
-            }
-            Err(e) => {
-                match e {
-                    TryRecvError::Empty => {}
-                    TryRecvError::Disconnected => println!("Error receive from thread {}", e),
+                Err(e) => {
+                    match e {
+                        TryRecvError::Empty => {}
+                        TryRecvError::Disconnected => println!("Error receive from thread {}", e),
+                    }
+                    break;
                 }
             }
         }
+        // the pod watch pushes updates without any user input
+        ctx.request_repaint_after(Duration::from_millis(500));
 
         SidePanel::right("right")
-            .resizable(false)
-            .default_width(150.0)
+            .resizable(true)
+            .default_width(260.0)
             .show(ctx, |ui| {
                 ScrollArea::vertical().show(ui, |ui| {
                     ui.with_layout(Layout::top_down_justified(Align::LEFT), |ui| {
@@ -233,14 +624,11 @@ impl DemoApp {
                         }
 
                         if ui.button("show custom").clicked() {
-                            self.toggle_window(ctx, "_Custom_");
-                        }
-
-                        if ui.button("show window").clicked() {
-                            self.toggle_window(ctx, "panels");
+                            self.toggle_window("_Custom_");
                         }
                         ui.separator();
-                        ui.label("Active Forwards")
+                        ui.label(RichText::new("Active Forwards").heading());
+                        self.draw_forwards(ui);
                     });
                 });
             });
@@ -250,7 +638,7 @@ impl DemoApp {
             ui.heading("My Pods");
 
             ui.horizontal(|ui| {
-                let conf_path_label = ui.label("Kubeconfig path");
+                let conf_path_label = ui.label("Kubeconfig paths");
                 if let Some(path) = &mut self.conf_file_path {
                     ui.text_edit_singleline(path).labelled_by(conf_path_label.id);
                 }
@@ -263,56 +651,128 @@ impl DemoApp {
                         };
                     }
                 };
+
+                ui.separator();
+
+                self.draw_context_picker(ui);
             });
 
+            self.draw_kubeconfig_sources(ui);
+
+            if let Some(error) = &self.last_error {
+                ui.colored_label(Color32::RED, error);
+            }
+
             ui.separator();
 
             ui.horizontal(|ui| {
                 ui.set_max_width(600.0);
 
-                let namespace_label = ui.label("Namespace");
-                if let Some(namespace) = &mut self.namespace {
-                    ui.text_edit_singleline(namespace).labelled_by(namespace_label.id);
+                ui.label("Namespace");
+                if self.namespace_picker.draw(ui) {
+                    self.watch_pods();
+                }
+                if ui.button("⟳").on_hover_text("Reload namespaces").clicked() {
+                    match self.thread_sender.try_send(ThreadMessage::Api(ApiCommand::PullNamespaces)) {
+                        Ok(_) => println!("ok send"),
+                        Err(_) => println!("err send"),
+                    };
                 }
 
                 ui.separator();
 
-                let filter_pod_label = ui.label("Filter pod prefix");
-                if let Some(prefix) = &mut self.filter_pod_prefix {
-                    ui.text_edit_singleline(prefix).labelled_by(filter_pod_label.id);
+                let filter_pod_label = ui.label("Filter pods");
+                let name_filter = ui.text_edit_singleline(&mut self.pod_query.name_filter).labelled_by(filter_pod_label.id);
+                let mut mode_changed = false;
+                ComboBox::from_id_salt("name_match_mode")
+                    .selected_text(self.pod_query.name_mode.to_string())
+                    .show_ui(ui, |ui| {
+                        for mode in NameMatchMode::ALL {
+                            mode_changed |= ui.selectable_value(&mut self.pod_query.name_mode, mode, mode.to_string()).changed();
+                        }
+                    });
+                if name_filter.changed() || mode_changed {
+                    self.query_error = self.pod_query.validate().err();
+                }
+                if name_filter.lost_focus() && ui.input(|i| i.key_pressed(Key::Enter)) {
+                    self.watch_pods();
                 }
 
                 if ui.button("Refresh").clicked() {
-                    if let Some(namespace) = self.namespace.as_ref() {
-                        match self.thread_sender.try_send(ThreadMessage::Api(ApiCommand::ReloadApisWithNameSpace(namespace.clone()))) {
-                            Ok(_) => println!("ok send"),
-                            Err(_) => println!("err send"),
-                        };
-                    }
-
-                    //https://rust-unofficial.github.io/patterns/idioms/temporary-mutability.html
-                    if let Some(prefix) = self.filter_pod_prefix.as_ref() {
-                        let clone = prefix.clone();
-                        self.pods.clear();
-                        self.container_columns = None;
-                        match self.thread_sender.try_send(ThreadMessage::Api(ApiCommand::PullPodsWithPrefix(clone))) {
-                            Ok(_) => println!("ok send"),
-                            Err(_) => println!("err send"),
-                        };
-                    }
+                    self.watch_pods();
                 };
+                if ui.button("Tail logs").on_hover_text("Stream the logs of every pod the filter lists, pods started later included").clicked()
+                    && self.query_error.is_none() {
+                    self.open_log_window(LogTarget::Pods(self.pod_query.clone()));
+                }
+            });
+
+            ui.horizontal(|ui| {
+                let labels_label = ui.label("Labels");
+                let labels = ui.add(TextEdit::singleline(&mut self.pod_query.label_selector).hint_text("app=foo,tier!=db"))
+                    .labelled_by(labels_label.id);
+
+                let fields_label = ui.label("Fields");
+                let fields = ui.add(TextEdit::singleline(&mut self.pod_query.field_selector).hint_text("status.phase=Running"))
+                    .on_hover_text(format!("Supported: {}", POD_SELECTABLE_FIELDS.join(", ")))
+                    .labelled_by(fields_label.id);
+
+                if labels.changed() || fields.changed() {
+                    self.query_error = self.pod_query.validate().err();
+                }
+                let submitted = (labels.lost_focus() || fields.lost_focus()) && ui.input(|i| i.key_pressed(Key::Enter));
+                if submitted {
+                    self.watch_pods();
+                }
+            });
+
+            ui.horizontal(|ui| {
+                let sidecars_label = ui.label("Collapse sidecars");
+                if ui.add(TextEdit::singleline(&mut self.sidecar_patterns).hint_text("istio-*,*-exporter"))
+                    .labelled_by(sidecars_label.id)
+                    .changed() {
+                    self.sidecar_matchers = sidecar_matchers(self.sidecar_patterns.as_str());
+                }
+
+                ui.separator();
+
+                ui.checkbox(&mut self.group_by_owner, "Group by owner");
             });
+
+            if let Some(error) = &self.query_error {
+                ui.colored_label(Color32::RED, error);
+            }
             //TOP END
 
             ui.separator();
 
-            ScrollArea::vertical().show(ui, |ui| {
-                ScrollArea::horizontal().show(ui, |ui| {
-                    ui.horizontal(|ui| {
-                        self.redraw_columns(ctx, ui);
-                    });
-                });
+            ui.horizontal(|ui| {
+                let mut view = self.view;
+                ui.selectable_value(&mut view, MainView::Pods, RichText::new("Pods").heading());
+                ui.selectable_value(&mut view, MainView::Services, RichText::new("Services").heading());
+                if view != self.view {
+                    self.view = view;
+                    if view == MainView::Services {
+                        self.pull_services();
+                    }
+                }
+                if self.view == MainView::Services && ui.button("⟳").on_hover_text("Reload services").clicked() {
+                    self.pull_services();
+                }
             });
+
+            match self.view {
+                MainView::Pods => {
+                    ScrollArea::vertical().show(ui, |ui| {
+                        ScrollArea::horizontal().show(ui, |ui| {
+                            ui.horizontal(|ui| {
+                                self.redraw_columns(ctx, ui);
+                            });
+                        });
+                    });
+                }
+                MainView::Services => self.service_list.draw(ui, &self.forwards),
+            }
         });
 
 
@@ -321,6 +781,48 @@ impl DemoApp {
 }
 
 
+/// Returns true when the logs of the workload should be tailed.
+fn draw_workload_heading(ui: &mut Ui, key: Option<&WorkloadKey>, info: Option<&WorkloadInfo>, pods: usize) -> bool {
+    let Some(key) = key else {
+        ui.heading(format!("Standalone pods ({})", pods));
+        return false;
+    };
+    ui.horizontal(|ui| {
+        ui.heading(key.to_string());
+        ui.label(RichText::new(&key.namespace).weak());
+        match info {
+            Some(info) => {
+                let color = if info.is_healthy() { Color32::DARK_GREEN } else { Color32::ORANGE };
+                ui.colored_label(color, info.summary());
+            }
+            None => {
+                ui.label(format!("{} pods", pods));
+            }
+        }
+        ui.button("Logs").on_hover_text("Stream the logs of all its pods").clicked()
+    }).inner
+}
+
+/// `1.5 KiB`
+fn format_bytes(bytes: f64) -> String {
+    const UNITS: [&str; 5] = ["B", "KiB", "MiB", "GiB", "TiB"];
+    let mut value = bytes;
+    let mut unit = 0;
+    while value >= 1024.0 && unit < UNITS.len() - 1 {
+        value /= 1024.0;
+        unit += 1;
+    }
+    if unit == 0 { format!("{:.0} {}", value, UNITS[unit]) } else { format!("{:.1} {}", value, UNITS[unit]) }
+}
+
+fn sidecar_matchers(patterns: &str) -> Vec<NameMatcher> {
+    patterns.split(',')
+        .map(str::trim)
+        .filter(|p| !p.is_empty())
+        .filter_map(|p| NameMatcher::new(NameMatchMode::Glob, p).ok())
+        .collect()
+}
+
 fn set_open(open: &mut HashSet<String>, key: &str, is_open: bool) {
     if is_open {
         if !open.contains(key) {
@@ -0,0 +1 @@
+pub mod k8ui;
@@ -1,5 +1,3 @@
-mod k8ui;
-
 fn main() {
-    k8ui::uinormdz::run_ui().unwrap()
+    rust_k8_ui::k8ui::uinormdz::run_ui().unwrap()
 }